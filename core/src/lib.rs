@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{any::Any, collections::HashMap, sync::Arc};
 
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
@@ -6,9 +6,62 @@ use serde::{Deserialize, Serialize};
 pub trait FunctionTool {
     fn key() -> String;
     fn desc() -> ToolImpl;
-    fn inject(args: std::collections::HashMap<String, serde_json::Value>) -> String;
+    fn inject(args: std::collections::HashMap<String, serde_json::Value>, ctx: &ToolContext)
+        -> String;
 }
-#[derive(Debug, Deserialize, Serialize)]
+
+/// What a tool gets besides its arguments: the call it answers and the state the
+/// application attached to the client, e.g. a database pool or the current user.
+///
+/// Tools see it as `ToolContext<S>` with their own state type, the chat passes it on
+/// with the type erased.
+#[derive(Debug)]
+pub struct ToolContext<S: ?Sized = dyn Any + Send + Sync> {
+    pub tool: String,
+    pub call_id: String,
+    state: Arc<S>,
+}
+
+impl<S: ?Sized> ToolContext<S> {
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<S: ?Sized> Clone for ToolContext<S> {
+    fn clone(&self) -> Self {
+        ToolContext {
+            tool: self.tool.clone(),
+            call_id: self.call_id.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl ToolContext {
+    /// `()` stands for no state.
+    pub fn new(
+        tool: impl Into<String>,
+        call_id: impl Into<String>,
+        state: Option<Arc<dyn Any + Send + Sync>>,
+    ) -> Self {
+        ToolContext {
+            tool: tool.into(),
+            call_id: call_id.into(),
+            state: state.unwrap_or_else(|| Arc::new(())),
+        }
+    }
+
+    /// The context with the state as `S`, `None` if the state is of another type.
+    pub fn downcast<S: Any + Send + Sync>(&self) -> Option<ToolContext<S>> {
+        Some(ToolContext {
+            tool: self.tool.clone(),
+            call_id: self.call_id.clone(),
+            state: self.state.clone().downcast().ok()?,
+        })
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type", content = "function")]
 pub enum ToolImpl {
     #[serde(rename = "function")]
@@ -19,7 +72,7 @@ pub enum ToolImpl {
     },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Parameters {
     #[serde(default = "default_type")]
     pub r#type: String,
@@ -33,7 +86,7 @@ pub fn default_type() -> String {
     DEFAULT_TYPE.to_string()
 }
 
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Property {
     pub r#type: String,
     #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
@@ -8,8 +8,7 @@ fn main() {
 }
 
 fn client() -> copilot_rs::Client {
-    let config = include_str!("../config.json");
-    serde_json::from_str(config).unwrap()
+    copilot_rs::Client::from_env().unwrap()
 }
 // complete会将函数体和参数注入到函数中
 #[complete(client="client", temperature=0.6, max_tokens=1000, tools = ["GetCurrentWeather"])]
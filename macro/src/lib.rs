@@ -1,143 +1,264 @@
 use std::collections::HashMap;
-use std::str::FromStr;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use copilot_rs_core::{default_type, Parameters, Property, ToolImpl};
 use darling::{ast::NestedMeta, FromMeta};
 use darling::{FromDeriveInput, FromField};
 use proc_macro::TokenStream;
-use quote::quote;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::punctuated::Punctuated;
+use syn::visit_mut::{self, VisitMut};
 use syn::{parse_macro_input, DeriveInput, Ident};
-use syn::{Expr, ItemFn, LitStr, Stmt};
+use syn::{Expr, ExprLit, ItemFn, Lit, LitStr, Token};
 #[proc_macro_attribute]
 pub fn complete(attr: TokenStream, item: TokenStream) -> proc_macro::TokenStream {
     match common_simple(attr, item) {
         Ok(output) => output,
-        Err(e) => TokenStream::from_str(e.to_string().as_str()).unwrap(),
+        Err(e) => {
+            let message = e.to_string();
+            quote! { compile_error!(#message); }.into()
+        }
     }
 }
 #[derive(Debug, FromMeta)]
 struct MacroArgs {
-    client: String,
+    client: ClientArg,
     model: Option<String>,
     temperature: Option<f32>,
+    top_p: Option<f32>,
     max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    frequency_penalty: Option<f32>,
+    presence_penalty: Option<f32>,
+    stop: Option<Vec<LitStr>>,
+    seed: Option<i64>,
+    n: Option<u32>,
+    logit_bias: Option<Vec<LitStr>>,
+    user: Option<String>,
+    reasoning_effort: Option<ReasoningEffort>,
+    logprobs: Option<bool>,
+    top_logprobs: Option<u8>,
+    context_budget: Option<usize>,
     tools: Option<Vec<LitStr>>,
     response_format: Option<String>,
 }
 
-fn common_simple(attr: TokenStream, item: TokenStream) -> Result<TokenStream> {
-    let attr_args = NestedMeta::parse_meta_list(attr.into())?;
-    let args = MacroArgs::from_list(&attr_args).unwrap();
-
-    let client = Ident::new(&args.client, proc_macro::Span::call_site().into());
+/// `client = "name"` calls the zero-arg function `name`, any other expression
+/// (`client = &APP.llm`, `client = self.client()`, a parameter) is evaluated where
+/// `.chat()` is called and may be a `Client`, a `&Client` or anything that borrows as one.
+#[derive(Debug)]
+enum ClientArg {
+    Function(Ident),
+    Expr(Expr),
+}
 
-    let mut item: ItemFn = syn::parse(item)?;
+impl FromMeta for ClientArg {
+    fn from_expr(expr: &Expr) -> darling::Result<Self> {
+        match expr {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(name),
+                ..
+            }) => Ok(ClientArg::Function(name.parse()?)),
+            expr => Ok(ClientArg::Expr(expr.clone())),
+        }
+    }
+}
 
-    let method_name = item.sig.ident.to_string();
-    let mut is_async = item.sig.asyncness.is_some();
-    let mut block = item.block;
+impl ClientArg {
+    /// The argument passed to the generated chat method.
+    fn borrowed(&self) -> Expr {
+        let client = match self {
+            ClientArg::Function(name) => quote! { #name() },
+            ClientArg::Expr(expr) => quote! { #expr },
+        };
+        syn::parse_quote! {
+            ::std::borrow::Borrow::<::copilot_rs::Client>::borrow(&(#client))
+        }
+    }
+}
 
-    let new_chat_method = format!("chat_{}", method_name);
+#[derive(Debug, FromMeta)]
+enum ReasoningEffort {
+    #[darling(rename = "minimal")]
+    Minimal,
+    #[darling(rename = "low")]
+    Low,
+    #[darling(rename = "medium")]
+    Medium,
+    #[darling(rename = "high")]
+    High,
+}
 
-    if let Stmt::Expr(expr, _) = block.stmts.last_mut().unwrap() {
-        if let Expr::Await(m) = expr {
-            if let Expr::MethodCall(m) = m.base.as_mut() {
-                let method = &m.method;
-                if method == "async_chat" {
-                    let ident = Ident::new(&new_chat_method, method.span());
-                    m.method = ident;
-                }
+impl MacroArgs {
+    fn options(&self) -> Result<TokenStream2> {
+        fn some<T: quote::ToTokens>(v: &Option<T>) -> TokenStream2 {
+            match v {
+                Some(v) => quote! { Some(#v) },
+                None => quote! { None },
             }
         }
-        if let Expr::MethodCall(m) = expr {
-            let method = &m.method;
-            if method == "chat" {
-                let ident = Ident::new(&new_chat_method, method.span());
-                m.method = ident;
-                is_async = false;
+
+        let model = some(&self.model.as_ref().map(|v| quote! { #v.to_string() }));
+        let temperature = some(&self.temperature);
+        let max_tokens = some(&self.max_tokens);
+        let top_p = some(&self.top_p);
+        let max_completion_tokens = some(&self.max_completion_tokens);
+        let frequency_penalty = some(&self.frequency_penalty);
+        let presence_penalty = some(&self.presence_penalty);
+        let stop = some(
+            &self
+                .stop
+                .as_ref()
+                .map(|v| quote! { vec![#(#v.to_string()),*] }),
+        );
+        let seed = some(&self.seed);
+        let n = some(&self.n);
+        let logit_bias = match &self.logit_bias {
+            Some(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|v| {
+                        let entry = v.value();
+                        let (token, bias) = entry.split_once(':').with_context(|| {
+                            format!("logit_bias entry `{}` is not `token:bias`", entry)
+                        })?;
+                        let bias: i32 = bias.trim().parse()?;
+                        let token = token.trim();
+                        Ok(quote! { (#token.to_string(), #bias) })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                quote! { Some(std::collections::HashMap::from([#(#entries),*])) }
             }
+            None => quote! { None },
+        };
+        let user = some(&self.user.as_ref().map(|v| quote! { #v.to_string() }));
+        let reasoning_effort = some(&self.reasoning_effort.as_ref().map(|v| {
+            let variant = Ident::new(&format!("{:?}", v), proc_macro::Span::call_site().into());
+            quote! { ::copilot_rs::ReasoningEffort::#variant }
+        }));
+        let logprobs = some(&self.logprobs);
+        let top_logprobs = some(&self.top_logprobs);
+        let context_budget = some(
+            &self
+                .context_budget
+                .map(|v| quote! { ::copilot_rs::ContextBudget::new(#v) }),
+        );
+
+        Ok(quote! {
+            ::copilot_rs::ChatOptions {
+                model: #model,
+                temperature: #temperature,
+                top_p: #top_p,
+                max_tokens: #max_tokens,
+                max_completion_tokens: #max_completion_tokens,
+                frequency_penalty: #frequency_penalty,
+                presence_penalty: #presence_penalty,
+                stop: #stop,
+                seed: #seed,
+                n: #n,
+                logit_bias: #logit_bias,
+                user: #user,
+                reasoning_effort: #reasoning_effort,
+                logprobs: #logprobs,
+                top_logprobs: #top_logprobs,
+                context_budget: #context_budget,
+            }
+        })
+    }
+}
+
+/// Rewrites every `.chat()`, `.chat_outcome()` and `.async_chat()` call in a function
+/// body into a direct call of the `copilot_rs` function of the same name, so the
+/// expansion introduces no names of its own.
+struct ChatRewriter {
+    client: Expr,
+    options: TokenStream2,
+    functions: TokenStream2,
+    rewritten: bool,
+}
+
+impl VisitMut for ChatRewriter {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        visit_mut::visit_expr_mut(self, expr);
+        let Expr::MethodCall(call) = expr else {
+            return;
+        };
+        if !call.args.is_empty() || call.turbofish.is_some() {
+            return;
+        }
+        let target = if call.method == "chat" {
+            quote! { chat }
+        } else if call.method == "chat_outcome" {
+            quote! { chat_outcome }
+        } else if call.method == "async_chat" {
+            quote! { async_chat }
+        } else {
+            return;
+        };
+        self.rewritten = true;
+        let ChatRewriter {
+            client,
+            options,
+            functions,
+            ..
+        } = self;
+        let receiver = &call.receiver;
+        *expr = syn::parse_quote_spanned! {call.method.span()=>
+            ::copilot_rs::#target(
+                #client,
+                &::copilot_rs::AsMessages::as_messages(&(#receiver)),
+                &#options,
+                #functions,
+            )
+        };
+    }
+
+    // macros with expression arguments, e.g. `format!` or `vec!`
+    fn visit_macro_mut(&mut self, mac: &mut syn::Macro) {
+        let Ok(mut args) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+        else {
+            return;
+        };
+        let rewritten = std::mem::take(&mut self.rewritten);
+        args.iter_mut().for_each(|arg| self.visit_expr_mut(arg));
+        if self.rewritten {
+            mac.tokens = args.to_token_stream();
         }
+        self.rewritten |= rewritten;
     }
 
-    // 更新函数体
-    item.block = block;
+    // nested items cannot see the function's parameters
+    fn visit_item_mut(&mut self, _item: &mut syn::Item) {}
+}
 
-    let new_chat_method_ident = Ident::new(&new_chat_method, proc_macro::Span::call_site().into());
+fn common_simple(attr: TokenStream, item: TokenStream) -> Result<TokenStream> {
+    let attr_args = NestedMeta::parse_meta_list(attr.into())?;
+    let args = MacroArgs::from_list(&attr_args).map_err(|e| anyhow::anyhow!("{}", e))?;
 
-    let new_chat_trait_name_ident = Ident::new(
-        &format!("Chat{}", fastrand::u32(..)),
-        proc_macro::Span::call_site().into(),
-    );
+    let mut item: ItemFn = syn::parse(item)?;
 
-    let client_model = client;
-    let model = args.model.clone().unwrap_or_default();
-    let temperature = args.temperature.unwrap_or(0.7);
-    let max_tokens = args.max_tokens.unwrap_or(1024);
     let functions = args
         .tools
         .as_ref()
         .map(|v| v.iter().map(|v| Ident::new(v.value().as_str(), v.span())))
-        .map(|tools|quote! {
+        .map(|tools| quote! {
             {
-                let mut hm = std::collections::HashMap::new();
-                #(hm.insert(#tools::key(),(#tools::desc(),#tools::inject as fn(std::collections::HashMap<String, serde_json::Value>) -> String));)*
+                let mut hm = ::std::collections::HashMap::new();
+                #(hm.insert(#tools::key(), (#tools::desc(), #tools::inject as ::copilot_rs::InjectionImpl));)*
                 hm
             }
-        }).unwrap_or(quote! { std::collections::HashMap::new() });
-    if is_async {
-        let trait_def = quote! {
-            trait #new_chat_trait_name_ident {
-                async fn #new_chat_method_ident(&self) -> String;
-            }
-        };
-        let impl_def = quote! {
-            impl #new_chat_trait_name_ident for Vec<copilot_rs::PromptMessage> {
-                async fn #new_chat_method_ident(&self) -> String {
-                    let model = #client_model();
-                    copilot_rs::async_chat(&model, &self).await
-                }
-            }
-        };
-        let expanded = quote! {
-            #item
+        }).unwrap_or(quote! { ::std::collections::HashMap::new() });
 
-            #trait_def
-
-            #impl_def
-        };
-
-        Ok(expanded.into())
-    } else {
-        let trait_def = quote! {
-            trait #new_chat_trait_name_ident {
-                fn #new_chat_method_ident(&self) -> String;
-            }
-        };
-
-        let impl_def = quote! {
-            impl #new_chat_trait_name_ident for Vec<copilot_rs::PromptMessage> {
-                fn #new_chat_method_ident(&self) -> String {
-                    let client = #client_model();
-                    let model = #model;
-                    let temperature = #temperature;
-                    let max_tokens = #max_tokens;
-                    let functions = #functions;
-                    copilot_rs::chat(&client,&self,model,temperature, max_tokens,functions)
-                }
-            }
-        };
-
-        let expanded = quote! {
-            #item
-
-            #trait_def
-
-            #impl_def
-        };
+    let mut rewriter = ChatRewriter {
+        client: args.client.borrowed(),
+        options: args.options()?,
+        functions,
+        rewritten: false,
+    };
+    rewriter.visit_block_mut(&mut item.block);
 
-        Ok(expanded.into())
-    }
+    Ok(quote! { #item }.into())
 }
 
 #[derive(FromDeriveInput, Debug)]
@@ -147,6 +268,8 @@ struct FunctionToolOptions {
     data: darling::ast::Data<(), FunctionToolProperties>,
     #[darling(default)]
     desc: String,
+    /// The client state the tool gets through `FunctionImplWithContext`.
+    state: Option<syn::Type>,
 }
 
 #[derive(Debug, FromField)]
@@ -217,6 +340,26 @@ pub fn derive_function_tool(input: TokenStream) -> TokenStream {
         },
     };
 
+    let exec = match &parsed.state {
+        None => quote! {
+            let _ = ctx;
+            c.exec()
+        },
+        Some(state) => {
+            let error = format!(
+                "error: the tool {} needs client state of type {}",
+                struct_str,
+                state.to_token_stream()
+            );
+            quote! {
+                match ctx.downcast::<#state>() {
+                    Some(ctx) => ::copilot_rs::FunctionImplWithContext::exec(&c, &ctx),
+                    None => #error.to_string(),
+                }
+            }
+        }
+    };
+
     let ret = quote! {
         impl FunctionTool for #struct_name {
             fn key() -> String {
@@ -225,12 +368,45 @@ pub fn derive_function_tool(input: TokenStream) -> TokenStream {
             fn desc() -> ToolImpl {
                 #desc_impl
             }
-            fn inject(args: std::collections::HashMap<String, serde_json::Value>) -> String {
-                let args = serde_json::to_string(&args).unwrap();
-                let c : #struct_name = serde_json::from_str(&args).unwrap();
-                c.exec()
+            fn inject(args: std::collections::HashMap<String, serde_json::Value>, ctx: &::copilot_rs::ToolContext) -> String {
+                let args = serde_json::Value::Object(args.into_iter().collect());
+                let c: #struct_name = match serde_json::from_value(args) {
+                    Ok(c) => c,
+                    Err(e) => return format!("error: invalid arguments for {}: {}", #struct_str, e),
+                };
+                #exec
             }
         }
     };
     ret.into()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use darling::{ast::NestedMeta, FromMeta};
+    use quote::quote;
+
+    use super::MacroArgs;
+
+    fn expand(attr: proc_macro2::TokenStream) -> String {
+        let meta = NestedMeta::parse_meta_list(attr).unwrap();
+        let args = MacroArgs::from_list(&meta).unwrap();
+        args.options().unwrap().to_string().replace(' ', "")
+    }
+
+    #[test]
+    fn unset_options_are_none() {
+        let options = expand(quote! { client = "client" });
+        assert!(options.contains("temperature:None"), "{}", options);
+        assert!(options.contains("max_tokens:None"), "{}", options);
+
+        let options = expand(quote! { client = "client", max_completion_tokens = 500 });
+        assert!(options.contains("temperature:None"), "{}", options);
+        assert!(options.contains("max_tokens:None"), "{}", options);
+        assert!(options.contains("max_completion_tokens:Some(500u32)"), "{}", options);
+
+        let options = expand(quote! { client = "client", temperature = 0.2, max_tokens = 64 });
+        assert!(options.contains("temperature:Some(0.2f32)"), "{}", options);
+        assert!(options.contains("max_tokens:Some(64u32)"), "{}", options);
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+use super::{named, since, total, Agent, ChatResult, StopReason, Termination};
+use crate::{ChatOptions, Client, Conversation, PromptMessage, Role};
+
+const MANAGER_PROMPT: &str = "You are coordinating a group chat. Read the conversation \
+and decide who should speak next. Answer with the name of one participant only.";
+
+/// How a [`GroupChat`] picks who speaks next.
+#[derive(Debug, Clone, Default)]
+pub enum SpeakerSelection {
+    /// Every agent in turn, in the order they were added.
+    #[default]
+    RoundRobin,
+    /// Any agent but the one that just spoke.
+    Random,
+    /// A manager model reads the chat and the agents' descriptions and names the
+    /// next speaker, falling back to round robin when its answer names nobody or
+    /// the chat is about to stop.
+    Auto(Box<Client>),
+}
+
+/// A chat between any number of agents.
+///
+/// Every message is broadcast to all members, with the speaker in
+/// `PromptMessage::name`. The chat stops after `max_rounds` replies or when a
+/// condition of the group or of any member matches the last message.
+#[derive(Debug, Clone)]
+pub struct GroupChat {
+    agents: Vec<Agent>,
+    selection: SpeakerSelection,
+    max_rounds: usize,
+    terminations: Vec<Termination>,
+    manager_prompt: String,
+}
+
+impl GroupChat {
+    pub fn new(agents: Vec<Agent>) -> Self {
+        GroupChat {
+            agents,
+            selection: SpeakerSelection::default(),
+            max_rounds: 10,
+            terminations: vec![],
+            manager_prompt: MANAGER_PROMPT.to_string(),
+        }
+    }
+
+    pub fn selection(mut self, selection: SpeakerSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// The most replies in one [`GroupChat::run`]. Defaults to 10.
+    pub fn max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    pub fn terminate_on(mut self, termination: Termination) -> Self {
+        self.terminations.push(termination);
+        self
+    }
+
+    /// The system prompt of the manager in [`SpeakerSelection::Auto`].
+    pub fn manager_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.manager_prompt = prompt.into();
+        self
+    }
+
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    pub fn agent(&self, name: &str) -> Option<&Agent> {
+        self.agents.iter().find(|agent| agent.name == name)
+    }
+
+    /// Posts `message` from `sender` and lets the agents talk. `sender` may be one of
+    /// the agents or anyone else, e.g. `"user"`.
+    pub fn run(&mut self, sender: &str, message: impl Into<String>) -> Result<ChatResult> {
+        if self.agents.is_empty() {
+            bail!("group chat without agents");
+        }
+        let start = total(&self.agents);
+        let mut manager = Conversation::new().system(self.manager_prompt.as_str());
+        self.agents.iter_mut().for_each(|agent| agent.replies = 0);
+
+        let mut transcript = vec![];
+        let mut last = self.agents.iter().position(|agent| agent.name == sender);
+        self.broadcast(last, sender, message.into(), &mut transcript);
+
+        let stop_reason = loop {
+            if transcript.len() > self.max_rounds {
+                break StopReason::MaxTurns;
+            }
+            let message = transcript.last().expect("the first message");
+            let stop = self
+                .terminations
+                .iter()
+                .find_map(|termination| termination.check(message, transcript.len() - 1))
+                .or_else(|| {
+                    self.agents
+                        .iter()
+                        .find_map(|agent| agent.termination(message))
+                });
+
+            let next = self.next_speaker(last, &transcript, &mut manager, stop.is_some())?;
+            let reply = match self.agents[next].speak(stop)? {
+                Ok(reply) => reply,
+                Err(reason) => break reason,
+            };
+            let name = self.agents[next].name.clone();
+            self.broadcast(Some(next), &name, reply, &mut transcript);
+            last = Some(next);
+        };
+
+        let mut usage = since(total(&self.agents), start);
+        usage += manager.usage();
+        Ok(ChatResult {
+            transcript,
+            stop_reason,
+            usage,
+        })
+    }
+
+    /// Adds a message to every member's history: the speaker said it, the others
+    /// heard it.
+    fn broadcast(
+        &mut self,
+        speaker: Option<usize>,
+        name: &str,
+        text: String,
+        transcript: &mut Vec<PromptMessage>,
+    ) {
+        let heard = named(name, Role::User, text);
+        for (i, agent) in self.agents.iter_mut().enumerate() {
+            match speaker {
+                // a reply is already in the speaker's history
+                Some(speaker) if speaker == i && !transcript.is_empty() => {}
+                Some(speaker) if speaker == i => agent.conversation.push(PromptMessage {
+                    role: Role::Assistant,
+                    ..heard.clone()
+                }),
+                _ => agent.conversation.push(heard.clone()),
+            }
+        }
+        transcript.push(PromptMessage {
+            role: speaker.map_or(Role::User, |_| Role::Assistant),
+            ..heard
+        });
+    }
+
+    fn next_speaker(
+        &self,
+        last: Option<usize>,
+        transcript: &[PromptMessage],
+        manager: &mut Conversation,
+        stopping: bool,
+    ) -> Result<usize> {
+        let n = self.agents.len();
+        let round_robin = last.map_or(0, |last| (last + 1) % n);
+        Ok(match &self.selection {
+            SpeakerSelection::RoundRobin => round_robin,
+            SpeakerSelection::Random => match last {
+                Some(last) if n > 1 => (last + fastrand::usize(1..n)) % n,
+                _ => fastrand::usize(..n),
+            },
+            // nobody but a person can keep the chat going, so the manager is not paid
+            SpeakerSelection::Auto(_) if stopping => round_robin,
+            SpeakerSelection::Auto(client) => {
+                let mut prompt = String::from("Participants:\n");
+                for agent in &self.agents {
+                    writeln!(prompt, "- {}: {}", agent.name, agent.about())?;
+                }
+                prompt.push_str("\nConversation:\n");
+                for message in transcript {
+                    let name = message.name.as_deref().unwrap_or("user");
+                    writeln!(prompt, "{}: {}", name, message.text())?;
+                }
+                prompt.push_str("\nWho speaks next?");
+
+                manager.rewind(0);
+                let options = ChatOptions::builder().temperature(0.0).build();
+                manager.set_options(options);
+                let answer = manager.send(client, prompt)?.into_text();
+                self.pick(answer.trim()).unwrap_or(round_robin)
+            }
+        })
+    }
+
+    /// The agent named in the manager's answer, preferring an exact match.
+    fn pick(&self, answer: &str) -> Option<usize> {
+        let answer = answer.trim_matches(|c: char| !c.is_alphanumeric() && c != '_' && c != '-');
+        self.agents
+            .iter()
+            .position(|agent| agent.name.eq_ignore_ascii_case(answer))
+            .or_else(|| {
+                self.agents
+                    .iter()
+                    .position(|agent| answer.contains(agent.name.as_str()))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::fake;
+
+    fn members(names: &[&str], replies: usize) -> (GroupChat, fake::Requests) {
+        let replies = (1..=replies).map(|i| json!(i.to_string())).collect();
+        let (client, requests) = fake::serve(replies);
+        let agents = names
+            .iter()
+            .map(|name| Agent::new(*name, client.clone()))
+            .collect();
+        (GroupChat::new(agents), requests)
+    }
+
+    fn speakers(result: &ChatResult) -> Vec<&str> {
+        let transcript = result.transcript.iter().skip(1);
+        transcript.map(|v| v.name.as_deref().unwrap()).collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let (group, requests) = members(&["a", "b", "c"], 4);
+        let mut group = group.max_rounds(4);
+        let result = group.run("user", "Hello").unwrap();
+
+        assert_eq!(result.stop_reason, StopReason::MaxTurns);
+        assert_eq!(speakers(&result), ["a", "b", "c", "a"]);
+        assert_eq!(result.last_message().unwrap(), "4");
+        assert_eq!(result.usage.total_tokens, 60);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        // a member starting the chat is followed by the next one
+        let (group, _) = members(&["a", "b", "c"], 2);
+        let result = group.max_rounds(2).run("b", "Hello").unwrap();
+        assert_eq!(speakers(&result), ["c", "a"]);
+    }
+
+    #[test]
+    fn random_never_repeats_a_speaker() {
+        let (group, _) = members(&["a", "b", "c"], 30);
+        let mut group = group.selection(SpeakerSelection::Random).max_rounds(30);
+        let result = group.run("a", "Hello").unwrap();
+
+        let mut speakers = speakers(&result);
+        assert_eq!(speakers.len(), 30);
+        speakers.insert(0, "a");
+        assert!(speakers.windows(2).all(|v| v[0] != v[1]), "{:?}", speakers);
+    }
+
+    #[test]
+    fn pick() {
+        let (group, _) = members(&["writer", "critic", "critic_2"], 0);
+        assert_eq!(group.pick("critic"), Some(1));
+        assert_eq!(group.pick("Critic."), Some(1));
+        assert_eq!(group.pick("**critic_2**"), Some(2));
+        assert_eq!(group.pick("I think the writer should go on"), Some(0));
+        assert_eq!(group.pick("nobody"), None);
+    }
+
+    #[test]
+    fn auto() {
+        let (manager, asked) = fake::serve(vec![json!("critic"), json!("nobody"), json!("critic")]);
+        let (group, _) = members(&["writer", "critic"], 3);
+        let mut group = group
+            .selection(SpeakerSelection::Auto(Box::new(manager)))
+            .terminate_on(Termination::keyword("3"));
+        let result = group.run("user", "Write a poem").unwrap();
+
+        // an answer naming nobody falls back to round robin
+        assert_eq!(speakers(&result), ["critic", "writer", "critic"]);
+        assert_eq!(result.stop_reason, StopReason::Keyword("3".to_string()));
+        // the manager is not asked once the chat stops
+        assert_eq!(asked.lock().unwrap().len(), 3);
+        assert_eq!(result.usage.total_tokens, 90);
+    }
+}
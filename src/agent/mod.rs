@@ -0,0 +1,546 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, BufRead, Write},
+    mem,
+    sync::Arc,
+};
+
+use anyhow::Result;
+
+mod group;
+mod react;
+mod swarm;
+
+pub use group::{GroupChat, SpeakerSelection};
+pub use react::{ReAct, ReActResult, Step, StepFn};
+pub use swarm::Swarm;
+
+use crate::{
+    ChatOptions, Client, Conversation, FunctionTool, PromptMessage, Role, SummaryMemory, Tools,
+    Usage,
+};
+
+/// Reads what a person wants to say, given a prompt to show them.
+pub type InputFn = Arc<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+/// When an agent asks a person instead of, or before, letting the model answer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HumanInput {
+    #[default]
+    Never,
+    /// Before every reply; an empty answer lets the model reply.
+    Always,
+    /// When the chat is about to stop; an empty answer stops it, anything else is
+    /// sent as the reply and the chat goes on.
+    OnTermination,
+}
+
+/// When a chat ends. An agent's conditions are checked against every message of the
+/// chats it takes part in, whoever sent it.
+#[derive(Clone)]
+pub enum Termination {
+    /// The message contains the keyword, e.g. `TERMINATE`.
+    Keyword(String),
+    /// The agent, or in a group chat the group, has replied this many times.
+    MaxTurns(usize),
+    Predicate(Arc<dyn Fn(&PromptMessage) -> bool + Send + Sync>),
+}
+
+impl Termination {
+    pub fn keyword(keyword: impl Into<String>) -> Self {
+        Termination::Keyword(keyword.into())
+    }
+
+    pub fn predicate(f: impl Fn(&PromptMessage) -> bool + Send + Sync + 'static) -> Self {
+        Termination::Predicate(Arc::new(f))
+    }
+}
+
+impl Termination {
+    /// Whether the chat ends at `message`, after `replies` replies.
+    pub(crate) fn check(&self, message: &PromptMessage, replies: usize) -> Option<StopReason> {
+        match self {
+            Termination::Keyword(keyword) => message
+                .text()
+                .contains(keyword.as_str())
+                .then(|| StopReason::Keyword(keyword.clone())),
+            Termination::MaxTurns(turns) => (replies >= *turns).then_some(StopReason::MaxTurns),
+            Termination::Predicate(f) => f(message).then_some(StopReason::Predicate),
+        }
+    }
+}
+
+impl fmt::Debug for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::Keyword(keyword) => f.debug_tuple("Keyword").field(keyword).finish(),
+            Termination::MaxTurns(turns) => f.debug_tuple("MaxTurns").field(turns).finish(),
+            Termination::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+/// Why a chat between agents ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The `max_turns` or `max_rounds` of the chat, or a [`Termination::MaxTurns`],
+    /// was reached.
+    MaxTurns,
+    Keyword(String),
+    Predicate,
+}
+
+/// The outcome of [`Agent::initiate_chat`].
+#[derive(Debug, Clone)]
+pub struct ChatResult {
+    /// Every message of the chat in order, with its sender in `name`.
+    pub transcript: Vec<PromptMessage>,
+    pub stop_reason: StopReason,
+    /// Tokens used by all agents during the chat.
+    pub usage: Usage,
+}
+
+impl ChatResult {
+    pub fn last_message(&self) -> Option<Cow<'_, str>> {
+        self.transcript.last().map(PromptMessage::text)
+    }
+}
+
+/// A named participant in a chat with its own model settings, tools and history.
+///
+/// ```no_run
+/// use copilot_rs::{agent::{Agent, Termination}, Client};
+///
+/// let client = Client::from_env()?;
+/// let mut cathy = Agent::new("cathy", client.clone())
+///     .system("Your name is Cathy and you are a part of a duo of comedians.");
+/// let mut joe = Agent::new("joe", client)
+///     .system("Your name is Joe and you are a part of a duo of comedians. Say BYE when done.")
+///     .terminate_on(Termination::keyword("BYE"));
+/// let result = cathy.initiate_chat(&mut joe, "Tell me a joke", 3)?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Clone)]
+pub struct Agent {
+    name: String,
+    description: Option<String>,
+    client: Client,
+    conversation: Conversation,
+    terminations: Vec<Termination>,
+    handoffs: Vec<String>,
+    human_input: HumanInput,
+    input: InputFn,
+    replies: usize,
+}
+
+impl Agent {
+    pub fn new(name: impl Into<String>, client: Client) -> Self {
+        Agent {
+            name: name.into(),
+            description: None,
+            client,
+            conversation: Conversation::new(),
+            terminations: vec![],
+            handoffs: vec![],
+            human_input: HumanInput::Never,
+            input: Arc::new(stdin_input),
+            replies: 0,
+        }
+    }
+
+    /// What the agent does, shown to whoever picks the next speaker of a group chat.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn system(mut self, prompt: impl Into<String>) -> Self {
+        self.conversation = self.conversation.system(prompt);
+        self
+    }
+
+    pub fn options(mut self, options: ChatOptions) -> Self {
+        self.conversation = self.conversation.options(options);
+        self
+    }
+
+    pub fn tool<T: FunctionTool>(mut self) -> Self {
+        self.conversation = self.conversation.tool::<T>();
+        self
+    }
+
+    pub fn tools(mut self, tools: Tools) -> Self {
+        self.conversation = self.conversation.tools(tools);
+        self
+    }
+
+    pub fn memory(mut self, memory: SummaryMemory) -> Self {
+        self.conversation = self.conversation.memory(memory);
+        self
+    }
+
+    pub fn terminate_on(mut self, termination: Termination) -> Self {
+        self.terminations.push(termination);
+        self
+    }
+
+    /// Lets this agent hand a [`Swarm`] over to the agent called `name`, through a
+    /// generated `transfer_to_<name>` tool.
+    pub fn handoff(mut self, name: impl Into<String>) -> Self {
+        self.handoffs.push(name.into());
+        self
+    }
+
+    pub fn human_input(mut self, mode: HumanInput) -> Self {
+        self.human_input = mode;
+        self
+    }
+
+    /// Where human input comes from. Defaults to reading a line from stdin.
+    pub fn input_with(
+        mut self,
+        input: impl Fn(&str) -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.input = Arc::new(input);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The description, or else the system prompt.
+    pub fn about(&self) -> &str {
+        self.description
+            .as_deref()
+            .or(self.conversation.system_prompt())
+            .unwrap_or_default()
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Everything this agent has seen and said, e.g. to [`Conversation::save`] it.
+    pub fn conversation(&self) -> &Conversation {
+        &self.conversation
+    }
+
+    pub fn conversation_mut(&mut self) -> &mut Conversation {
+        &mut self.conversation
+    }
+
+    /// Forgets the history, keeping the settings.
+    pub fn reset(&mut self) {
+        self.conversation.rewind(0);
+        self.replies = 0;
+    }
+
+    /// Sends `message` to `other` and lets the two reply to each other until one of
+    /// them terminates or both replied `max_turns` times.
+    pub fn initiate_chat(
+        &mut self,
+        other: &mut Agent,
+        message: impl Into<String>,
+        max_turns: usize,
+    ) -> Result<ChatResult> {
+        let start = total([&*self, &*other]);
+        self.replies = 0;
+        other.replies = 0;
+
+        let message = named(&self.name, Role::Assistant, message.into());
+        self.conversation.push(message.clone());
+        let mut transcript = vec![message];
+
+        let (mut sender, mut receiver) = (self, other);
+        let stop_reason = loop {
+            if transcript.len() > max_turns * 2 {
+                break StopReason::MaxTurns;
+            }
+            let last = transcript.last().expect("the first message");
+            let stop = receiver
+                .termination(last)
+                .or_else(|| sender.termination(last));
+            let reply = match receiver.reply(&sender.name, last, stop)? {
+                Ok(reply) => reply,
+                Err(reason) => break reason,
+            };
+            transcript.push(named(&receiver.name, Role::Assistant, reply));
+            mem::swap(&mut sender, &mut receiver);
+        };
+
+        Ok(ChatResult {
+            transcript,
+            stop_reason,
+            usage: since(total([&*sender, &*receiver]), start),
+        })
+    }
+
+    /// Answers a message from `sender`, unless `stop` says the chat ends here and no
+    /// person overrides it.
+    pub(crate) fn reply(
+        &mut self,
+        sender: &str,
+        message: &PromptMessage,
+        stop: Option<StopReason>,
+    ) -> Result<Result<String, StopReason>> {
+        let incoming = named(sender, Role::User, message.text().into_owned());
+        self.respond(Some(incoming), stop)
+    }
+
+    /// Like [`Agent::reply`] for a message that is already in the history, as in a
+    /// group chat where every message is broadcast to all members.
+    pub(crate) fn speak(&mut self, stop: Option<StopReason>) -> Result<Result<String, StopReason>> {
+        self.respond(None, stop)
+    }
+
+    fn respond(
+        &mut self,
+        incoming: Option<PromptMessage>,
+        stop: Option<StopReason>,
+    ) -> Result<Result<String, StopReason>> {
+        let last = incoming.as_ref().or(self.conversation.messages().last());
+        let heard = last.map_or(String::new(), |message| {
+            format!(
+                "{} -> {}: {}\n",
+                message.name.as_deref().unwrap_or("user"),
+                self.name,
+                message.text()
+            )
+        });
+
+        if let Some(reason) = stop {
+            if self.human_input != HumanInput::OnTermination {
+                return Ok(Err(reason));
+            }
+            let prompt = format!(
+                "{}{} would stop here. reply to continue, or press enter to stop: ",
+                heard, self.name
+            );
+            let answer = (self.input)(&prompt)?;
+            if answer.trim().is_empty() {
+                return Ok(Err(reason));
+            }
+            return Ok(Ok(self.reply_as_human(incoming, answer)));
+        }
+
+        if self.human_input == HumanInput::Always {
+            let prompt = format!(
+                "{}reply as {}, or press enter to let the model answer: ",
+                heard, self.name
+            );
+            let answer = (self.input)(&prompt)?;
+            if !answer.trim().is_empty() {
+                return Ok(Ok(self.reply_as_human(incoming, answer)));
+            }
+        }
+
+        let reply = match incoming {
+            Some(incoming) => self.conversation.send_message(&self.client, incoming)?,
+            None => self.conversation.reply(&self.client)?,
+        };
+        self.replies += 1;
+        Ok(Ok(reply.into_text()))
+    }
+
+    fn reply_as_human(&mut self, incoming: Option<PromptMessage>, answer: String) -> String {
+        if let Some(incoming) = incoming {
+            self.conversation.push(incoming);
+        }
+        self.conversation
+            .push(PromptMessage::new(Role::Assistant, answer.as_str()));
+        self.replies += 1;
+        answer
+    }
+
+    /// Whether this agent's conditions end the chat at `message`.
+    pub(crate) fn termination(&self, message: &PromptMessage) -> Option<StopReason> {
+        self.terminations
+            .iter()
+            .find_map(|termination| termination.check(message, self.replies))
+    }
+}
+
+impl fmt::Debug for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Agent")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("conversation", &self.conversation)
+            .field("terminations", &self.terminations)
+            .field("handoffs", &self.handoffs)
+            .field("human_input", &self.human_input)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn named(name: &str, role: Role, text: String) -> PromptMessage {
+    PromptMessage {
+        name: Some(name.to_string()),
+        ..PromptMessage::new(role, text)
+    }
+}
+
+pub(crate) fn total<'a>(agents: impl IntoIterator<Item = &'a Agent>) -> Usage {
+    let mut usage = Usage::default();
+    for agent in agents {
+        usage += agent.conversation.usage();
+    }
+    usage
+}
+
+pub(crate) fn since(now: Usage, start: Usage) -> Usage {
+    Usage {
+        prompt_tokens: now.prompt_tokens - start.prompt_tokens,
+        completion_tokens: now.completion_tokens - start.completion_tokens,
+        total_tokens: now.total_tokens - start.total_tokens,
+    }
+}
+
+fn stdin_input(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::fake;
+
+    fn duo(replies: Vec<serde_json::Value>) -> (Agent, Agent, fake::Requests) {
+        let (client, requests) = fake::serve(replies);
+        let cathy = Agent::new("cathy", client.clone());
+        let joe = Agent::new("joe", client);
+        (cathy, joe, requests)
+    }
+
+    fn texts(result: &ChatResult) -> Vec<String> {
+        let text = |v: &PromptMessage| format!("{}: {}", v.name.as_deref().unwrap(), v.text());
+        result.transcript.iter().map(text).collect()
+    }
+
+    #[test]
+    fn max_turns() {
+        let (mut cathy, mut joe, requests) =
+            duo(vec![json!("1"), json!("2"), json!("3"), json!("4")]);
+        let result = cathy.initiate_chat(&mut joe, "Count with me", 2).unwrap();
+
+        assert_eq!(result.stop_reason, StopReason::MaxTurns);
+        assert_eq!(
+            texts(&result),
+            [
+                "cathy: Count with me",
+                "joe: 1",
+                "cathy: 2",
+                "joe: 3",
+                "cathy: 4"
+            ]
+        );
+        assert_eq!(result.usage.total_tokens, 60);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        // each agent hears the other as the user
+        let history = joe.conversation().messages();
+        assert_eq!(history[0].role, Role::User);
+        assert_eq!(history[0].name.as_deref(), Some("cathy"));
+        assert_eq!(history[1].role, Role::Assistant);
+    }
+
+    #[test]
+    fn terminations() {
+        let (mut cathy, joe, requests) = duo(vec![json!("Hi"), json!("A joke"), json!("BYE")]);
+        let mut joe = joe.terminate_on(Termination::keyword("BYE"));
+        let result = cathy.initiate_chat(&mut joe, "Hello", 10).unwrap();
+        assert_eq!(result.stop_reason, StopReason::Keyword("BYE".to_string()));
+        assert_eq!(result.last_message().unwrap(), "BYE");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // the other agent's conditions count too
+        let (cathy, mut joe, _) = duo(vec![json!("How are you?"), json!("Fine")]);
+        let mut cathy = cathy.terminate_on(Termination::predicate(|v| v.text().ends_with('?')));
+        let result = cathy.initiate_chat(&mut joe, "Hello", 10).unwrap();
+        assert_eq!(result.stop_reason, StopReason::Predicate);
+        assert_eq!(texts(&result), ["cathy: Hello", "joe: How are you?"]);
+
+        let (mut cathy, joe, _) = duo(vec![json!("Hi"), json!("Hi again"), json!("Bye")]);
+        let mut joe = joe.terminate_on(Termination::MaxTurns(2));
+        let result = cathy.initiate_chat(&mut joe, "Hello", 10).unwrap();
+        assert_eq!(result.stop_reason, StopReason::MaxTurns);
+        assert_eq!(result.last_message().unwrap(), "Bye");
+    }
+
+    fn answers(answers: &[&'static str]) -> (InputFn, Arc<Mutex<Vec<String>>>) {
+        let answers = Mutex::new(answers.iter().copied().collect::<VecDeque<_>>());
+        let prompts = Arc::new(Mutex::new(vec![]));
+        let seen = prompts.clone();
+        let input: InputFn = Arc::new(move |prompt: &str| {
+            seen.lock().unwrap().push(prompt.to_string());
+            Ok(answers.lock().unwrap().pop_front().unwrap().to_string())
+        });
+        (input, prompts)
+    }
+
+    #[test]
+    fn human_input_overrides_a_stop() {
+        let (cathy, joe, requests) = duo(vec![json!("BYE"), json!("BYE then")]);
+        let mut joe = joe.terminate_on(Termination::keyword("BYE"));
+        let (input, prompts) = answers(&["Wait, one more", ""]);
+        let mut cathy = cathy
+            .human_input(HumanInput::OnTermination)
+            .input_with(move |prompt| input(prompt));
+        let result = cathy.initiate_chat(&mut joe, "Hello", 10).unwrap();
+
+        assert_eq!(result.stop_reason, StopReason::Keyword("BYE".to_string()));
+        assert_eq!(
+            texts(&result),
+            [
+                "cathy: Hello",
+                "joe: BYE",
+                "cathy: Wait, one more",
+                "joe: BYE then"
+            ]
+        );
+        // the person answered instead of the model
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(
+            prompts[0],
+            "joe -> cathy: BYE\ncathy would stop here. reply to continue, or press enter to stop: "
+        );
+        let last = cathy.conversation().messages().last().unwrap();
+        assert_eq!(last.role, Role::Assistant);
+        assert_eq!(last.text(), "Wait, one more");
+    }
+
+    #[test]
+    fn human_input_always() {
+        let (cathy, mut joe, requests) = duo(vec![json!("Hi"), json!("Bye"), json!("Sure")]);
+        let (input, prompts) = answers(&["", "I am a person"]);
+        let mut cathy = cathy
+            .human_input(HumanInput::Always)
+            .input_with(move |prompt| input(prompt));
+        let result = cathy.initiate_chat(&mut joe, "Hello", 2).unwrap();
+
+        // an empty answer lets the model reply
+        assert_eq!(
+            texts(&result),
+            [
+                "cathy: Hello",
+                "joe: Hi",
+                "cathy: Bye",
+                "joe: Sure",
+                "cathy: I am a person"
+            ]
+        );
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(prompts.lock().unwrap().len(), 2);
+    }
+}
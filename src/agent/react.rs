@@ -0,0 +1,349 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    call_tool, complete, ChatOptions, Client, Content, FunctionTool, IntoPrompt, PromptMessage,
+    Role, ToolCall, Tools, Usage,
+};
+
+const INSTRUCTIONS: &str = "Solve the task step by step. Before every tool call, write one \
+short thought about what you need and why. Use the tool results to decide what to do next. \
+When you know the answer, reply without calling a tool, starting with `Final Answer:`.";
+const FINAL_ANSWER: &str = "Final Answer:";
+
+/// Called with every step as soon as its observation is known.
+pub type StepFn = Arc<dyn Fn(&Step) + Send + Sync>;
+
+/// One thought, action and observation of a [`ReAct`] run.
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// What the model wrote alongside the tool call, if anything.
+    pub thought: Option<String>,
+    pub action: ToolCall,
+    /// The tool result, or the error that kept the tool from running.
+    pub observation: String,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(thought) = &self.thought {
+            writeln!(f, "Thought: {}", thought)?;
+        }
+        writeln!(
+            f,
+            "Action: {}({})",
+            self.action.function.name, self.action.function.arguments
+        )?;
+        writeln!(f, "Observation: {}", self.observation)
+    }
+}
+
+/// The outcome of [`ReAct::run`].
+#[derive(Debug, Clone)]
+pub struct ReActResult {
+    /// The last reply with the `Final Answer:` prefix removed.
+    pub answer: String,
+    pub steps: Vec<Step>,
+    /// Every message sent and received, without the system prompt.
+    pub messages: Vec<PromptMessage>,
+    pub usage: Usage,
+}
+
+impl ReActResult {
+    /// The steps as `Thought:`, `Action:` and `Observation:` lines.
+    pub fn scratchpad(&self) -> String {
+        self.steps.iter().map(Step::to_string).collect()
+    }
+}
+
+/// Runs a ReAct loop: the model thinks and calls a tool, the result is fed back as an
+/// observation, until the model gives a final answer.
+///
+/// Mistakes of the model, such as unknown tools or malformed arguments, and tools
+/// that panic or time out become observations so the model can correct itself.
+/// Cancellation and middleware errors fail the run.
+///
+/// ```no_run
+/// use copilot_rs::{agent::ReAct, Client};
+///
+/// let client = Client::from_env()?;
+/// let react = ReAct::new(client).max_steps(5);
+/// let result = react.run("How many days are there between 2024-02-03 and 2024-03-01?")?;
+/// print!("{}", result.scratchpad());
+/// println!("{}", result.answer);
+/// # anyhow::Ok(())
+/// ```
+#[derive(Clone)]
+pub struct ReAct {
+    client: Client,
+    system: Option<String>,
+    options: ChatOptions,
+    tools: Tools,
+    max_steps: usize,
+    on_step: Option<StepFn>,
+}
+
+impl ReAct {
+    pub fn new(client: Client) -> Self {
+        ReAct {
+            client,
+            system: None,
+            options: ChatOptions::default(),
+            tools: Tools::new(),
+            max_steps: 10,
+            on_step: None,
+        }
+    }
+
+    /// Sent before the ReAct instructions, e.g. to describe the role or domain.
+    pub fn system(mut self, prompt: impl Into<String>) -> Self {
+        self.system = Some(prompt.into());
+        self
+    }
+
+    pub fn options(mut self, options: ChatOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn tool<T: FunctionTool>(mut self) -> Self {
+        self.tools.insert(T::key(), (T::desc(), T::inject));
+        self
+    }
+
+    pub fn tools(mut self, tools: Tools) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    /// The most tool calls one run makes. Defaults to 10.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Traces the scratchpad while the run goes on, e.g. to log it.
+    pub fn on_step(mut self, f: impl Fn(&Step) + Send + Sync + 'static) -> Self {
+        self.on_step = Some(Arc::new(f));
+        self
+    }
+
+    /// Works on `task` until the model answers. After `max_steps` tool calls the
+    /// model is asked once more without tools, and the run fails if it still does
+    /// not answer.
+    pub fn run(&self, task: impl Into<Content>) -> Result<ReActResult> {
+        let system = match &self.system {
+            Some(system) => format!("{}\n\n{}", system, INSTRUCTIONS),
+            None => INSTRUCTIONS.to_string(),
+        };
+        let system = PromptMessage::new(Role::System, system);
+        let mut messages = vec![PromptMessage::new(Role::User, task)];
+        let mut steps = vec![];
+        let mut usage = Usage::default();
+
+        loop {
+            // once out of steps, the model has to answer with what it has
+            let out_of_steps = steps.len() >= self.max_steps;
+            let tools = if out_of_steps {
+                vec![]
+            } else {
+                self.tools.values().map(|(tool, _)| tool).collect()
+            };
+            let prompt: Vec<_> = std::iter::once(system.clone())
+                .chain(messages.iter().cloned())
+                .collect();
+            let (message, cost) = complete(&self.client, &prompt, &self.options, tools)?;
+            usage += cost;
+            let message = message.context("no message")?;
+            let calls = message.tool_calls.clone().unwrap_or_default();
+            let thought = Some(message.text().trim().to_string()).filter(|v| !v.is_empty());
+            messages.push(message);
+
+            if calls.is_empty() {
+                return Ok(ReActResult {
+                    answer: final_answer(thought.as_deref().unwrap_or_default()),
+                    steps,
+                    messages,
+                    usage,
+                });
+            }
+            if out_of_steps {
+                bail!("no final answer after {} steps", self.max_steps);
+            }
+
+            for call in calls {
+                let observation = call_tool(&self.client, &self.tools, &call)?
+                    .text()
+                    .into_owned();
+                messages.push(observation.as_str().tool(call.id.clone()));
+                let step = Step {
+                    thought: thought.clone(),
+                    action: call,
+                    observation,
+                };
+                if let Some(on_step) = &self.on_step {
+                    on_step(&step);
+                }
+                steps.push(step);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ReAct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReAct")
+            .field("system", &self.system)
+            .field("options", &self.options)
+            .field("tools", &self.tools)
+            .field("max_steps", &self.max_steps)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The text after the last `Final Answer:`, or all of it if the model left it out.
+fn final_answer(reply: &str) -> String {
+    match reply.rfind(FINAL_ANSWER) {
+        Some(i) => reply[i + FINAL_ANSWER.len()..].trim().to_string(),
+        None => reply.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{fake, CancellationToken, FunctionImplTrait, Middleware, ToolImpl};
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Get weather of an location")]
+    struct GetCurrentWeather {
+        #[props(desc = "The city and state")]
+        location: String,
+    }
+
+    impl FunctionImplTrait for GetCurrentWeather {
+        fn exec(&self) -> String {
+            format!("heavy rain in {}", self.location)
+        }
+    }
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Always fails")]
+    struct Broken {}
+
+    impl FunctionImplTrait for Broken {
+        fn exec(&self) -> String {
+            panic!("out of order")
+        }
+    }
+
+    #[test]
+    fn errors_are_observations() {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"city": "Tianjin"}"#),
+            fake::tool_call("call_2", "GetCurrentWeather", "location: Tianjin"),
+            fake::tool_call("call_3", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+            fake::tool_call("call_4", "Broken", "{}"),
+            fake::tool_call("call_5", "GetTime", "{}"),
+            json!("It rains.\nFinal Answer: heavy rain"),
+        ];
+        let (client, requests) = fake::serve(replies);
+        let result = ReAct::new(client)
+            .tool::<GetCurrentWeather>()
+            .tool::<Broken>()
+            .run("What is the weather in Tianjin?")
+            .unwrap();
+
+        assert_eq!(result.answer, "heavy rain");
+        let observations: Vec<_> = result
+            .steps
+            .iter()
+            .map(|v| v.observation.as_str())
+            .collect();
+        assert_eq!(
+            observations[0],
+            "error: invalid arguments for GetCurrentWeather: missing field `location`"
+        );
+        assert!(
+            observations[1].starts_with("error: "),
+            "{}",
+            observations[1]
+        );
+        assert_eq!(observations[2], "heavy rain in Tianjin");
+        assert_eq!(
+            observations[3],
+            "error: the tool Broken panicked: out of order"
+        );
+        assert_eq!(observations[4], "error: unknown tool GetTime");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 6);
+        let last = requests[5]["messages"].as_array().unwrap();
+        assert_eq!(last[last.len() - 1]["content"], observations[4]);
+    }
+
+    static RUN: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Cancels the run it is called from")]
+    struct Cancel {}
+
+    impl FunctionImplTrait for Cancel {
+        fn exec(&self) -> String {
+            RUN.lock().unwrap().as_ref().unwrap().cancel();
+            "cancelled".to_string()
+        }
+    }
+
+    #[test]
+    fn cancellation_fails_the_run() {
+        let replies = vec![
+            fake::tool_call("call_1", "Cancel", "{}"),
+            json!("Final Answer: too late"),
+        ];
+        let (client, requests) = fake::serve(replies);
+        *RUN.lock().unwrap() = Some(client.cancellation.clone());
+        let err = ReAct::new(client).tool::<Cancel>().run("Stop").unwrap_err();
+        assert_eq!(err.to_string(), "cancelled");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    struct Veto;
+
+    impl Middleware for Veto {
+        fn before_tool(&self, call: &mut ToolCall) -> Result<()> {
+            bail!("{} is not allowed", call.function.name)
+        }
+    }
+
+    #[test]
+    fn middleware_errors_fail_the_run() {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+            json!("Final Answer: too late"),
+        ];
+        let (client, requests) = fake::serve(replies);
+        let err = ReAct::new(client.with_middleware(Veto))
+            .tool::<GetCurrentWeather>()
+            .run("What is the weather in Tianjin?")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "GetCurrentWeather is not allowed");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn final_answers() {
+        assert_eq!(final_answer("Final Answer: 26"), "26");
+        assert_eq!(
+            final_answer("Thinking.\nFinal Answer:  26 days "),
+            "26 days"
+        );
+        assert_eq!(final_answer("26"), "26");
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+
+use super::Agent;
+use crate::{
+    call_tool, complete, ChatOutcome, Content, IntoPrompt, Parameters, PromptMessage, Role,
+    ToolImpl, Usage,
+};
+
+const TRANSFER_PREFIX: &str = "transfer_to_";
+
+/// Agents that hand one shared conversation over to each other.
+///
+/// Only the active agent answers, with its own system prompt, tools and options.
+/// For every agent in its [`Agent::handoff`] list it also gets a
+/// `transfer_to_<name>` tool; when the model calls one, that agent becomes active
+/// and answers next, seeing the whole history.
+///
+/// ```no_run
+/// use copilot_rs::{agent::{Agent, Swarm}, Client};
+///
+/// let client = Client::from_env()?;
+/// let triage = Agent::new("triage", client.clone())
+///     .system("Route the customer to the right department.")
+///     .handoff("billing");
+/// let billing = Agent::new("billing", client)
+///     .description("Refunds, invoices and payment methods.")
+///     .system("You handle billing questions.")
+///     .handoff("triage");
+/// let mut swarm = Swarm::new(vec![triage, billing]);
+/// swarm.send("I was charged twice")?;
+/// assert_eq!(swarm.active().name(), "billing");
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Swarm {
+    agents: Vec<Agent>,
+    active: usize,
+    history: Vec<PromptMessage>,
+    max_steps: usize,
+    usage: Usage,
+}
+
+impl Swarm {
+    /// The first agent starts out active.
+    pub fn new(agents: Vec<Agent>) -> Self {
+        Swarm {
+            agents,
+            active: 0,
+            history: vec![],
+            max_steps: 10,
+            usage: Usage::default(),
+        }
+    }
+
+    /// The most requests one [`Swarm::send`] makes. Defaults to 10.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Makes the agent called `name` answer the next message.
+    pub fn activate(&mut self, name: &str) -> Result<()> {
+        self.active = self
+            .agents
+            .iter()
+            .position(|agent| agent.name == name)
+            .with_context(|| format!("unknown agent {}", name))?;
+        Ok(())
+    }
+
+    pub fn active(&self) -> &Agent {
+        &self.agents[self.active]
+    }
+
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    /// The shared history, with the agent that wrote each reply in `name`.
+    pub fn history(&self) -> &[PromptMessage] {
+        &self.history
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// Sends a user message to the active agent and runs tool calls and handoffs
+    /// until an agent answers. Nothing changes when it fails, e.g. because an agent
+    /// hands off to one that is not in the swarm.
+    pub fn send(&mut self, message: impl Into<Content>) -> Result<ChatOutcome> {
+        if self.agents.is_empty() {
+            bail!("swarm without agents");
+        }
+        for agent in &self.agents {
+            transfer_tools(&self.agents, agent)?;
+        }
+        let (len, active, usage) = (self.history.len(), self.active, self.usage);
+        self.history.push(PromptMessage::new(Role::User, message));
+        let outcome = self.run();
+        if outcome.is_err() {
+            self.history.truncate(len);
+            self.active = active;
+            self.usage = usage;
+        }
+        outcome
+    }
+
+    fn run(&mut self) -> Result<ChatOutcome> {
+        for _ in 0..self.max_steps {
+            let agent = &self.agents[self.active];
+            let transfers = transfer_tools(&self.agents, agent)?;
+            let tools = agent
+                .conversation
+                .tools
+                .values()
+                .map(|(tool, _)| tool)
+                .chain(transfers.iter().map(|(tool, _)| tool))
+                .collect();
+            let prompt: Vec<_> = agent
+                .conversation
+                .system
+                .iter()
+                .map(|system| PromptMessage::new(Role::System, system.as_str()))
+                .chain(self.history.iter().cloned())
+                .collect();
+
+            let (message, usage) =
+                complete(&agent.client, &prompt, &agent.conversation.options, tools)?;
+            self.usage += usage;
+            let message = PromptMessage {
+                name: Some(agent.name.clone()),
+                ..message.context("no message")?
+            };
+            let calls = message.tool_calls.clone().unwrap_or_default();
+            self.history.push(message);
+            if calls.is_empty() {
+                return Ok(self.history.last().expect("the reply").into());
+            }
+
+            let mut next = self.active;
+            for call in &calls {
+                let target = transfers
+                    .iter()
+                    .find(|(tool, _)| tool_name(tool) == call.function.name);
+                let tool_message = match target {
+                    Some(&(_, target)) => {
+                        next = target;
+                        format!("Transferred to {}.", self.agents[target].name)
+                            .tool(call.id.clone())
+                    }
+                    None => call_tool(&agent.client, &agent.conversation.tools, call)?,
+                };
+                self.history.push(tool_message);
+            }
+            self.active = next;
+        }
+        bail!("no answer after {} steps", self.max_steps)
+    }
+}
+
+/// The `transfer_to_<name>` tools of `agent`, with the index of the agent each one
+/// hands over to.
+fn transfer_tools(agents: &[Agent], agent: &Agent) -> Result<Vec<(ToolImpl, usize)>> {
+    let mut transfers: Vec<(ToolImpl, usize)> = vec![];
+    for name in &agent.handoffs {
+        let target = agents
+            .iter()
+            .position(|other| &other.name == name)
+            .with_context(|| format!("{} hands off to unknown agent {}", agent.name, name))?;
+        let other = &agents[target];
+        let transfer = format!("{}{}", TRANSFER_PREFIX, sanitize(&other.name));
+        if let Some((_, taken)) = transfers
+            .iter()
+            .find(|(tool, _)| tool_name(tool) == transfer)
+        {
+            bail!(
+                "the handoffs of {} to {} and {} are both called {}",
+                agent.name,
+                agents[*taken].name,
+                other.name,
+                transfer
+            );
+        }
+        let tool = ToolImpl::Function {
+            name: transfer,
+            description: format!(
+                "Hand the conversation over to {}. {}",
+                other.name,
+                other.about()
+            ),
+            parameters: Parameters {
+                r#type: "object".to_string(),
+                properties: HashMap::new(),
+                required: vec![],
+            },
+        };
+        transfers.push((tool, target));
+    }
+    Ok(transfers)
+}
+
+fn tool_name(tool: &ToolImpl) -> &str {
+    match tool {
+        ToolImpl::Function { name, .. } => name,
+    }
+}
+
+/// Tool names may only contain letters, digits, `_` and `-`.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::fake;
+
+    fn swarm(client: &crate::Client) -> Swarm {
+        let triage = Agent::new("triage", client.clone()).handoff("billing");
+        let billing = Agent::new("billing", client.clone()).handoff("triage");
+        Swarm::new(vec![triage, billing])
+    }
+
+    #[test]
+    fn handoff() {
+        let transfer = fake::tool_call("call_1", "transfer_to_billing", "{}");
+        let (client, requests) = fake::serve(vec![transfer, json!("Refunded.")]);
+        let mut swarm = swarm(&client);
+        let outcome = swarm.send("I was charged twice").unwrap();
+        assert_eq!(outcome, ChatOutcome::Message("Refunded.".to_string()));
+        assert_eq!(swarm.active().name(), "billing");
+        assert_eq!(swarm.history().len(), 4);
+        assert_eq!(swarm.history()[3].name.as_deref(), Some("billing"));
+        assert_eq!(swarm.usage().total_tokens, 30);
+
+        let requests = requests.lock().unwrap();
+        let tools = &requests[1]["tools"];
+        assert_eq!(tools[0]["function"]["name"], "transfer_to_triage");
+    }
+
+    #[test]
+    fn failure_changes_nothing() {
+        let transfer = fake::tool_call("call_1", "transfer_to_billing", "{}");
+        let (client, _) = fake::serve(vec![json!("Hello."), transfer]);
+        let mut swarm = swarm(&client);
+        swarm.send("Hi").unwrap();
+        let (history, usage) = (swarm.history().to_vec(), swarm.usage());
+
+        // the transfer is answered, then the next request fails
+        assert!(swarm.send("I was charged twice").is_err());
+        assert_eq!(swarm.history(), history);
+        assert_eq!(swarm.usage(), usage);
+        assert_eq!(swarm.active().name(), "triage");
+    }
+
+    #[test]
+    fn bad_handoffs() {
+        let (client, requests) = fake::serve(vec![json!("Hello.")]);
+        let triage = Agent::new("triage", client.clone()).handoff("sales");
+        let mut swarm = Swarm::new(vec![triage]);
+        let err = swarm.send("Hi").unwrap_err();
+        assert_eq!(err.to_string(), "triage hands off to unknown agent sales");
+        assert!(swarm.history().is_empty());
+
+        let agents = vec![
+            Agent::new("triage", client.clone())
+                .handoff("billing team")
+                .handoff("billing_team"),
+            Agent::new("billing team", client.clone()),
+            Agent::new("billing_team", client),
+        ];
+        let err = Swarm::new(agents).send("Hi").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the handoffs of triage to billing team and billing_team are both called transfer_to_billing_team"
+        );
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
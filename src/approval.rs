@@ -0,0 +1,196 @@
+use std::{borrow::Cow, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Client, ToolCall};
+
+/// Whether a tool runs as soon as the model calls it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolPolicy {
+    #[default]
+    Auto,
+    /// Ask the [`Approver`] of the client first.
+    Ask,
+    /// Never run it; the model is told so.
+    Deny,
+}
+
+/// What an [`Approver`] decided about a tool call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Approve,
+    /// Do not run the tool and send the reason to the model as its result.
+    Reject(String),
+    /// Run the tool with these arguments instead.
+    Edit(Value),
+}
+
+/// Decides about tool calls whose policy is [`ToolPolicy::Ask`], e.g. by asking a
+/// person.
+pub trait Approver: Send + Sync {
+    fn approve(&self, call: &ToolCall) -> Decision;
+}
+
+impl<F: Fn(&ToolCall) -> Decision + Send + Sync> Approver for F {
+    fn approve(&self, call: &ToolCall) -> Decision {
+        self(call)
+    }
+}
+
+impl fmt::Debug for dyn Approver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Approver")
+    }
+}
+
+/// The arguments `call` may run with, or why it must not run.
+pub(crate) fn review<'a>(client: &Client, call: &'a ToolCall) -> Result<Cow<'a, str>, String> {
+    let name = &call.function.name;
+    let policy = client
+        .tool_policies
+        .get(name)
+        .or_else(|| client.tool_policies.get("*"))
+        .copied()
+        .unwrap_or_default();
+    match policy {
+        ToolPolicy::Auto => Ok(Cow::Borrowed(&call.function.arguments)),
+        ToolPolicy::Deny => Err(format!("the tool {} is not allowed", name)),
+        ToolPolicy::Ask => {
+            let Some(approver) = &client.approver else {
+                return Err(format!(
+                    "the tool {} needs approval, but nobody can approve it",
+                    name
+                ));
+            };
+            match approver.approve(call) {
+                Decision::Approve => Ok(Cow::Borrowed(&call.function.arguments)),
+                Decision::Reject(reason) => Err(reason),
+                Decision::Edit(arguments) => Ok(Cow::Owned(arguments.to_string())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{call_tool, Function, InjectionImpl, ToolContext, ToolImpl, Tools};
+
+    fn call(name: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                arguments: r#"{"location":"Tianjin"}"#.to_string(),
+            },
+        }
+    }
+
+    fn with_policies(
+        policies: &[(&str, ToolPolicy)],
+        approver: Option<Arc<dyn Approver>>,
+    ) -> Client {
+        Client {
+            tool_policies: policies
+                .iter()
+                .map(|(name, policy)| (name.to_string(), *policy))
+                .collect(),
+            approver,
+            ..Client::builder()
+                .api_base("http://localhost".to_string())
+                .api_key("test-key")
+                .model_default("gpt-4o".to_string())
+                .build()
+        }
+    }
+
+    fn decide(decision: Decision) -> Option<Arc<dyn Approver>> {
+        Some(Arc::new(move |_: &ToolCall| decision.clone()))
+    }
+
+    #[test]
+    fn policies() {
+        let arguments = r#"{"location":"Tianjin"}"#;
+        let client = with_policies(&[], None);
+        assert_eq!(review(&client, &call("weather")).unwrap(), arguments);
+
+        let client = with_policies(
+            &[("weather", ToolPolicy::Auto), ("*", ToolPolicy::Deny)],
+            None,
+        );
+        assert_eq!(review(&client, &call("weather")).unwrap(), arguments);
+        assert_eq!(
+            review(&client, &call("delete")).unwrap_err(),
+            "the tool delete is not allowed"
+        );
+
+        let client = with_policies(&[("*", ToolPolicy::Ask)], None);
+        assert_eq!(
+            review(&client, &call("weather")).unwrap_err(),
+            "the tool weather needs approval, but nobody can approve it"
+        );
+    }
+
+    #[test]
+    fn decisions() {
+        let ask = [("weather", ToolPolicy::Ask)];
+        let client = with_policies(&ask, decide(Decision::Approve));
+        assert_eq!(
+            review(&client, &call("weather")).unwrap(),
+            r#"{"location":"Tianjin"}"#
+        );
+        // only tools asked about reach the approver
+        let client = with_policies(&ask, decide(Decision::Reject("no".to_string())));
+        assert_eq!(review(&client, &call("weather")).unwrap_err(), "no");
+        assert!(review(&client, &call("time")).is_ok());
+
+        let edit = Decision::Edit(json!({ "location": "Beijing" }));
+        let client = with_policies(&ask, decide(edit));
+        assert_eq!(
+            review(&client, &call("weather")).unwrap(),
+            r#"{"location":"Beijing"}"#
+        );
+    }
+
+    fn weather(args: HashMap<String, Value>, _: &ToolContext) -> String {
+        format!("heavy rain in {}", args["location"].as_str().unwrap())
+    }
+
+    #[test]
+    fn tools_see_the_decision() {
+        let tool = ToolImpl::Function {
+            name: "weather".to_string(),
+            description: String::new(),
+            parameters: crate::Parameters {
+                r#type: "object".to_string(),
+                properties: HashMap::new(),
+                required: vec![],
+            },
+        };
+        let tools = Tools::from([("weather".to_string(), (tool, weather as InjectionImpl))]);
+        let run = |client: &Client| call_tool(client, &tools, &call("weather")).unwrap();
+
+        let ask = [("weather", ToolPolicy::Ask)];
+        let edit = Decision::Edit(json!({ "location": "Beijing" }));
+        let message = run(&with_policies(&ask, decide(edit)));
+        assert_eq!(message.text(), "heavy rain in Beijing");
+        assert_eq!(message.tool_call_id.as_deref(), Some("call_1"));
+
+        let message = run(&with_policies(&ask, decide(Decision::Approve)));
+        assert_eq!(message.text(), "heavy rain in Tianjin");
+
+        let reject = Decision::Reject("ask the user first".to_string());
+        let message = run(&with_policies(&ask, decide(reject)));
+        assert_eq!(message.text(), "Not run: ask the user first");
+
+        let message = run(&with_policies(&[("*", ToolPolicy::Deny)], None));
+        assert_eq!(message.text(), "Not run: the tool weather is not allowed");
+    }
+}
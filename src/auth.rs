@@ -0,0 +1,304 @@
+use std::{
+    fmt,
+    process::Command,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A credential that never shows up in `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Secret(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+/// A short-lived token and the moment it stops being valid.
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub secret: Secret,
+    pub expires_at: Option<SystemTime>,
+}
+
+/// Fetches tokens for [`Auth::Refreshing`], e.g. from an OAuth or Entra ID endpoint.
+pub trait TokenProvider: Send + Sync {
+    fn fetch(&self) -> Result<Token>;
+}
+
+/// Caches the token of a [`TokenProvider`] until shortly before it expires.
+pub struct RefreshingToken {
+    provider: Box<dyn TokenProvider>,
+    skew: Duration,
+    cached: Mutex<Option<Token>>,
+}
+
+impl RefreshingToken {
+    pub fn new(provider: impl TokenProvider + 'static) -> Self {
+        RefreshingToken {
+            provider: Box::new(provider),
+            skew: Duration::from_secs(60),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Refresh this long before the token expires. Defaults to one minute.
+    pub fn skew(mut self, skew: Duration) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    pub fn token(&self) -> Result<Secret> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        let deadline = SystemTime::now() + self.skew;
+        if let Some(token) = cached.as_ref().filter(|token| {
+            token
+                .expires_at
+                .is_none_or(|expires_at| deadline < expires_at)
+        }) {
+            return Ok(token.secret.clone());
+        }
+        let token = self.provider.fetch().context("refresh token")?;
+        let secret = token.secret.clone();
+        *cached = Some(token);
+        Ok(secret)
+    }
+}
+
+/// Where the credential sent to the provider comes from.
+///
+/// In configs a plain string is a static key, `{"env": "VAR"}` reads an environment
+/// variable and `{"command": ["op", "read", "..."]}` runs a command once and uses its
+/// trimmed stdout.
+#[derive(Clone)]
+pub enum Auth {
+    Static(Secret),
+    Env(String),
+    Command(Vec<String>, Arc<OnceCell<Secret>>),
+    Refreshing(Arc<RefreshingToken>),
+}
+
+impl Auth {
+    pub fn env(var: impl Into<String>) -> Self {
+        Auth::Env(var.into())
+    }
+
+    pub fn command<I, S>(command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Auth::Command(
+            command.into_iter().map(Into::into).collect(),
+            Default::default(),
+        )
+    }
+
+    pub fn refreshing(provider: impl TokenProvider + 'static) -> Self {
+        Auth::Refreshing(Arc::new(RefreshingToken::new(provider)))
+    }
+
+    /// Resolves the credential to send with the next request.
+    pub fn token(&self) -> Result<Secret> {
+        match self {
+            Auth::Static(secret) => Ok(secret.clone()),
+            Auth::Env(var) => std::env::var(var)
+                .map(Secret)
+                .with_context(|| format!("read api key from ${}", var)),
+            Auth::Command(command, cached) => cached.get_or_try_init(|| run(command)).cloned(),
+            Auth::Refreshing(token) => token.token(),
+        }
+    }
+
+    /// Whether serializing this would write a secret, or something that cannot be
+    /// serialized, into a config.
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Auth::Static(_) | Auth::Refreshing(_))
+    }
+}
+
+fn run(command: &[String]) -> Result<Secret> {
+    let (program, args) = command.split_first().context("empty auth command")?;
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("run auth command `{}`", program))?;
+    if !output.status.success() {
+        bail!(
+            "auth command `{}` failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Secret(String::from_utf8(output.stdout)?.trim().to_string()))
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Static(secret) => f.debug_tuple("Static").field(secret).finish(),
+            Auth::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            Auth::Command(command, _) => f.debug_tuple("Command").field(command).finish(),
+            Auth::Refreshing(_) => f.write_str("Refreshing"),
+        }
+    }
+}
+
+impl From<String> for Auth {
+    fn from(value: String) -> Self {
+        Auth::Static(Secret(value))
+    }
+}
+
+impl From<&str> for Auth {
+    fn from(value: &str) -> Self {
+        Auth::Static(Secret(value.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AuthConfig {
+    Key(String),
+    Env { env: String },
+    Command { command: Vec<String> },
+}
+
+impl<'de> Deserialize<'de> for Auth {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match AuthConfig::deserialize(deserializer)? {
+            AuthConfig::Key(key) => Auth::from(key),
+            AuthConfig::Env { env } => Auth::Env(env),
+            AuthConfig::Command { command } => Auth::command(command),
+        })
+    }
+}
+
+impl Serialize for Auth {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Auth::Env(env) => AuthConfig::Env { env: env.clone() }.serialize(serializer),
+            Auth::Command(command, _) => AuthConfig::Command {
+                command: command.clone(),
+            }
+            .serialize(serializer),
+            Auth::Static(_) | Auth::Refreshing(_) => Err(serde::ser::Error::custom(
+                "refusing to serialize a secret api key",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::Client;
+
+    fn client(api_key: Auth) -> Client {
+        Client::builder()
+            .api_base("https://api.openai.com/v1".to_string())
+            .api_key(api_key)
+            .model_default("gpt-4o".to_string())
+            .build()
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let debug = format!("{:?}", Secret::new("sk-live-123"));
+        assert_eq!(debug, "Secret(***)");
+        let debug = format!("{:?}", client("sk-live-123".into()));
+        assert!(!debug.contains("sk-live-123"), "{}", debug);
+    }
+
+    #[test]
+    fn serialization() {
+        let json = serde_json::to_value(client("sk-live-123".into())).unwrap();
+        assert!(json.get("api_key").is_none(), "{}", json);
+        assert!(!json.to_string().contains("sk-live-123"), "{}", json);
+
+        let json = serde_json::to_value(client(Auth::env("OPENAI_API_KEY"))).unwrap();
+        assert_eq!(
+            json["api_key"],
+            serde_json::json!({ "env": "OPENAI_API_KEY" })
+        );
+        let back: Client = serde_json::from_value(json).unwrap();
+        assert!(matches!(back.api_key, Auth::Env(var) if var == "OPENAI_API_KEY"));
+
+        let command = ["op", "read", "op://vault/openai/key"];
+        let json = serde_json::to_value(client(Auth::command(command))).unwrap();
+        assert_eq!(json["api_key"], serde_json::json!({ "command": command }));
+        let back: Client = serde_json::from_value(json).unwrap();
+        assert!(matches!(back.api_key, Auth::Command(v, _) if v == command));
+    }
+
+    #[test]
+    fn command() {
+        let auth = Auth::command(["echo", "  token  "]);
+        assert_eq!(auth.token().unwrap().expose(), "token");
+        assert!(Auth::command(["false"]).token().is_err());
+    }
+
+    /// Hands out `token-1`, `token-2`, ... valid for `ttl`.
+    struct Counter {
+        fetched: Arc<AtomicU32>,
+        ttl: Duration,
+    }
+
+    impl TokenProvider for Counter {
+        fn fetch(&self) -> Result<Token> {
+            let n = self.fetched.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Token {
+                secret: Secret::new(format!("token-{}", n)),
+                expires_at: Some(SystemTime::now() + self.ttl),
+            })
+        }
+    }
+
+    fn refreshing(ttl: Duration) -> (RefreshingToken, Arc<AtomicU32>) {
+        let fetched = Arc::new(AtomicU32::new(0));
+        let provider = Counter {
+            fetched: fetched.clone(),
+            ttl,
+        };
+        (RefreshingToken::new(provider), fetched)
+    }
+
+    #[test]
+    fn refreshes_after_expiry() {
+        let (token, fetched) = refreshing(Duration::from_secs(3600));
+        assert_eq!(token.token().unwrap().expose(), "token-1");
+        assert_eq!(token.token().unwrap().expose(), "token-1");
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+
+        // valid for less than the skew, so already due
+        let (token, fetched) = refreshing(Duration::from_secs(30));
+        assert_eq!(token.token().unwrap().expose(), "token-1");
+        assert_eq!(token.token().unwrap().expose(), "token-2");
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        let (token, _) = refreshing(Duration::from_millis(50));
+        let token = token.skew(Duration::ZERO);
+        assert_eq!(token.token().unwrap().expose(), "token-1");
+        assert_eq!(token.token().unwrap().expose(), "token-1");
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(token.token().unwrap().expose(), "token-2");
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+/// A future that runs a blocking call on its own thread.
+///
+/// Requests go through blocking reqwest, which must not run on an async executor, so
+/// this keeps the async API usable from any runtime without depending on one.
+pub(crate) struct Background<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+struct State<T> {
+    output: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> Background<T> {
+    pub(crate) fn spawn(f: impl FnOnce() -> T + Send + 'static) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            output: None,
+            waker: None,
+        }));
+        let shared = state.clone();
+        thread::spawn(move || {
+            let output = panic::catch_unwind(AssertUnwindSafe(f));
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Background { state }
+    }
+}
+
+impl<T> Future for Background<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.output.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
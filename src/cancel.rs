@@ -0,0 +1,327 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{Client, InjectionImpl, ToolCall, ToolContext};
+
+// how often a running tool is checked for cancellation of the client
+const POLL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Tells running chats and tools to stop. Clones share the same state.
+///
+/// Cancelling [`Client::cancellation`] fails the chats using that client, including
+/// async ones, before their next request, as soon as a tool with a timeout is
+/// abandoned, or once a tool without one returns. Tools see their own token in
+/// [`CancellationToken::current`] and should return early once it is cancelled, as a
+/// running tool cannot be stopped from outside.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Box<CancellationToken>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether this token or one it was made from is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// A token cancelled with this one, that can also be cancelled on its own.
+    pub fn child(&self) -> Self {
+        CancellationToken {
+            cancelled: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// The token of the tool running on this thread, cancelled when the tool timed
+    /// out or the chat was cancelled. Never cancelled outside of a tool.
+    pub fn current() -> Self {
+        CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancellationToken")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Runs a tool, reporting a panic as its output. A tool with a timeout runs on its
+/// own thread and is waited for at most that long: `None` if it timed out, it then
+/// keeps running with its token cancelled.
+pub(crate) fn run_tool(
+    client: &Client,
+    call: &ToolCall,
+    f: InjectionImpl,
+    args: HashMap<String, Value>,
+) -> Result<Option<String>> {
+    let name = call.function.name.clone();
+    let ctx = ToolContext::new(name.as_str(), call.id.as_str(), client.state.clone());
+    let token = client.cancellation.child();
+    let timeout = client
+        .tool_timeouts
+        .get(&name)
+        .copied()
+        .or(client.tool_timeout);
+
+    let Some(timeout) = timeout else {
+        let previous = CURRENT.with(|v| v.replace(Some(token)));
+        let output = exec(&name, f, args, &ctx);
+        CURRENT.with(|v| *v.borrow_mut() = previous);
+        if client.cancellation.is_cancelled() {
+            bail!("cancelled");
+        }
+        return Ok(Some(output));
+    };
+    let deadline = Instant::now() + timeout;
+
+    let (tx, rx) = mpsc::channel();
+    let current = token.clone();
+    #[cfg(feature = "tracing")]
+    let span = tracing::Span::current();
+    let tool = name.clone();
+    thread::spawn(move || {
+        #[cfg(feature = "tracing")]
+        let _span = span.entered();
+        CURRENT.with(|v| *v.borrow_mut() = Some(current));
+        let _ = tx.send(exec(&tool, f, args, &ctx));
+    });
+
+    loop {
+        if client.cancellation.is_cancelled() {
+            bail!("cancelled");
+        }
+        let wait = deadline.saturating_duration_since(Instant::now()).min(POLL);
+        match rx.recv_timeout(wait) {
+            // a tool that stopped because the chat was cancelled did not finish
+            Ok(_) if client.cancellation.is_cancelled() => bail!("cancelled"),
+            Ok(output) => return Ok(Some(output)),
+            Err(RecvTimeoutError::Timeout) => {
+                if Instant::now() >= deadline {
+                    token.cancel();
+                    return Ok(None);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("tool {} stopped", name),
+        }
+    }
+}
+
+fn exec(name: &str, f: InjectionImpl, args: HashMap<String, Value>, ctx: &ToolContext) -> String {
+    panic::catch_unwind(AssertUnwindSafe(|| f(args, ctx))).unwrap_or_else(|e| {
+        let reason = e
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        format!("error: the tool {} panicked: {}", name, reason)
+    })
+}
+
+/// Durations in configs are seconds, e.g. `tool_timeout = 2.5`.
+pub(crate) mod secs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        v.map(|v| v.as_secs_f64()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        let secs = Option::<f64>::deserialize(d)?;
+        secs.map(|v| Duration::try_from_secs_f64(v).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+pub(crate) mod secs_map {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        v: &HashMap<String, Duration>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let secs: HashMap<_, _> = v.iter().map(|(k, v)| (k, v.as_secs_f64())).collect();
+        secs.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<HashMap<String, Duration>, D::Error> {
+        HashMap::<String, f64>::deserialize(d)?
+            .into_iter()
+            .map(|(k, v)| {
+                Duration::try_from_secs_f64(v)
+                    .map(|v| (k, v))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::Function;
+
+    fn client() -> Client {
+        Client::builder()
+            .api_base("http://localhost".to_string())
+            .api_key("test-key")
+            .model_default("gpt-4o".to_string())
+            .build()
+    }
+
+    fn call(name: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                arguments: "{}".to_string(),
+            },
+        }
+    }
+
+    fn thread_id(_: HashMap<String, Value>, _: &ToolContext) -> String {
+        format!("{:?}", thread::current().id())
+    }
+
+    fn boom(_: HashMap<String, Value>, _: &ToolContext) -> String {
+        panic!("boom")
+    }
+
+    /// Runs until its token is cancelled, for at most five seconds.
+    fn slow(_: HashMap<String, Value>, _: &ToolContext) -> String {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if CancellationToken::current().is_cancelled() {
+                return "stopped".to_string();
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        "done".to_string()
+    }
+
+    fn run(client: &Client, name: &str, f: InjectionImpl) -> Result<Option<String>> {
+        run_tool(client, &call(name), f, HashMap::new())
+    }
+
+    #[test]
+    fn inline_without_timeout() {
+        let here = format!("{:?}", thread::current().id());
+        let output = run(&client(), "thread_id", thread_id).unwrap();
+        assert_eq!(output, Some(here.clone()));
+        assert!(!CancellationToken::current().is_cancelled());
+
+        let client = Client {
+            tool_timeout: Some(Duration::from_secs(5)),
+            ..client()
+        };
+        let output = run(&client, "thread_id", thread_id).unwrap();
+        assert_ne!(output, Some(here));
+    }
+
+    #[test]
+    fn panics_are_errors() {
+        let expected = Some("error: the tool boom panicked: boom".to_string());
+        assert_eq!(run(&client(), "boom", boom).unwrap(), expected);
+
+        let client = Client {
+            tool_timeout: Some(Duration::from_secs(5)),
+            ..client()
+        };
+        assert_eq!(run(&client, "boom", boom).unwrap(), expected);
+    }
+
+    static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+    fn timed_out(args: HashMap<String, Value>, ctx: &ToolContext) -> String {
+        let output = slow(args, ctx);
+        TIMED_OUT.store(output == "stopped", Ordering::SeqCst);
+        output
+    }
+
+    #[test]
+    fn timeout() {
+        let client = Client {
+            tool_timeout: Some(Duration::from_secs(5)),
+            tool_timeouts: HashMap::from([("slow".to_string(), Duration::from_millis(50))]),
+            ..client()
+        };
+        let start = Instant::now();
+        assert_eq!(run(&client, "slow", timed_out).unwrap(), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // the abandoned tool sees its token cancelled, the client's is not
+        while !TIMED_OUT.load(Ordering::SeqCst) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!client.cancellation.is_cancelled());
+    }
+
+    #[test]
+    fn cancellation() {
+        for timeout in [None, Some(Duration::from_secs(5))] {
+            let client = Client {
+                tool_timeout: timeout,
+                ..client()
+            };
+            let token = client.cancellation.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            });
+            let start = Instant::now();
+            let err = run(&client, "slow", slow).unwrap_err();
+            assert_eq!(err.to_string(), "cancelled");
+            assert!(start.elapsed() < Duration::from_secs(1), "{:?}", timeout);
+        }
+    }
+
+    #[test]
+    fn child_tokens() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        child.cancel();
+        assert!(!parent.is_cancelled());
+        let child = parent.child();
+        parent.cancel();
+        assert!(child.is_cancelled());
+    }
+}
@@ -0,0 +1,379 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::{Auth, Client};
+
+const DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+impl Client {
+    /// Builds a client from `OPENAI_API_KEY`, `OPENAI_BASE_URL` and `OPENAI_MODEL`.
+    ///
+    /// The key is read again for every request, so it is never stored in the client.
+    pub fn from_env() -> Result<Client> {
+        std::env::var("OPENAI_API_KEY").context("OPENAI_API_KEY is not set")?;
+        Ok(Client::builder()
+            .api_base(std::env::var("OPENAI_BASE_URL").unwrap_or(DEFAULT_API_BASE.to_string()))
+            .api_key(Auth::env("OPENAI_API_KEY"))
+            .model_default(std::env::var("OPENAI_MODEL").unwrap_or(DEFAULT_MODEL.to_string()))
+            .build())
+    }
+
+    /// Loads a single TOML or JSON config file, see [`ConfigLoader`].
+    pub fn from_file(path: impl AsRef<Path>, profile: Option<&str>) -> Result<Client> {
+        let mut loader = ConfigLoader::new().file(path);
+        if let Some(profile) = profile {
+            loader = loader.profile(profile);
+        }
+        loader.load()
+    }
+}
+
+/// Loads a `Client` from layered TOML or JSON files.
+///
+/// Later files override earlier ones, table by table, except that an `api_key` is
+/// replaced as a whole. Top-level keys are shared by every profile and
+/// `[profiles.<name>]` tables override them; the profile is picked by
+/// [`ConfigLoader::profile`], then `COPILOT_PROFILE`, then a top-level `profile` key.
+/// `COPILOT_API_BASE`, `COPILOT_API_KEY` and `COPILOT_MODEL` override the result, and
+/// `${VAR}` or `${VAR:-default}` in any string value is replaced from the environment.
+///
+/// ```toml
+/// profile = "dev"
+/// api_key = { env = "OPENAI_API_KEY" }
+///
+/// [profiles.dev]
+/// api_base = "https://api.openai.com/v1"
+/// model_default = "gpt-4o-mini"
+///
+/// [profiles.local-ollama]
+/// api_base = "http://${OLLAMA_HOST:-localhost}:11434"
+/// api_key = ""
+/// model_default = "llama3.2"
+/// provider = "ollama"
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    files: Vec<(PathBuf, bool)>,
+    profile: Option<String>,
+    env_prefix: String,
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        ConfigLoader {
+            files: vec![],
+            profile: None,
+            env_prefix: "COPILOT".to_string(),
+        }
+    }
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push((path.as_ref().to_path_buf(), true));
+        self
+    }
+
+    /// A file that is skipped when it does not exist, e.g. a local override.
+    pub fn optional_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push((path.as_ref().to_path_buf(), false));
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Prefix of the override variables. Defaults to `COPILOT`.
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    pub fn load(&self) -> Result<Client> {
+        let mut config = Value::Object(Map::new());
+        for (path, required) in &self.files {
+            if !required && !path.exists() {
+                continue;
+            }
+            merge(&mut config, read(path)?);
+        }
+        let Value::Object(mut config) = config else {
+            bail!("config root must be a table");
+        };
+
+        let profiles = config.remove("profiles");
+        let default_profile = config.remove("profile");
+        let profile = self
+            .profile
+            .clone()
+            .or_else(|| self.var("PROFILE"))
+            .or_else(|| default_profile.and_then(|v| v.as_str().map(String::from)));
+        let mut config = Value::Object(config);
+        if let Some(profile) = profile {
+            let overrides = profiles
+                .as_ref()
+                .and_then(|v| v.get(&profile))
+                .with_context(|| format!("unknown profile `{}`", profile))?;
+            merge(&mut config, overrides.clone());
+        }
+
+        for (var, key) in [
+            ("API_BASE", "api_base"),
+            ("API_KEY", "api_key"),
+            ("MODEL", "model_default"),
+        ] {
+            if let Some(value) = self.var(var) {
+                config[key] = Value::String(value);
+            }
+        }
+
+        interpolate(&mut config)?;
+        serde_json::from_value(config).context("invalid client config")
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(format!("{}_{}", self.env_prefix, name)).ok()
+    }
+}
+
+fn read(path: &Path) -> Result<Value> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    match path.extension().and_then(|v| v.to_str()) {
+        Some("toml") => toml::from_str(&text).with_context(|| format!("parse {}", path.display())),
+        Some("json") => {
+            serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))
+        }
+        _ => bail!("unsupported config format: {}", path.display()),
+    }
+}
+
+// keys whose tables are alternatives, e.g. `{ env = ... }` or `{ command = ... }`,
+// so a later layer replaces them instead of adding to them
+const REPLACED: &[&str] = &["api_key"];
+
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) if !REPLACED.contains(&key.as_str()) => merge(existing, value),
+                    _ => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+fn interpolate(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(text) => *text = expand(text)?,
+        Value::Array(values) => values.iter_mut().try_for_each(interpolate)?,
+        Value::Object(values) => values.values_mut().try_for_each(interpolate)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand(text: &str) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("unterminated `${{` in `{}`", text))?;
+        let expr = &rest[start + 2..start + end];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        match (std::env::var(name), default) {
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => bail!("environment variable `{}` is not set", name),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `text` to a file only this test uses.
+    fn write(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("copilot-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    // every test uses its own variables, as tests run in parallel
+    fn loader(prefix: &str) -> ConfigLoader {
+        ConfigLoader::new().env_prefix(prefix)
+    }
+
+    const BASE: &str = r#"
+profile = "dev"
+api_key = { env = "OPENAI_API_KEY" }
+api_base = "https://api.openai.com/v1"
+model_default = "gpt-4o-mini"
+
+[profiles.dev]
+model_default = "gpt-4o"
+
+[profiles.local]
+api_base = "http://localhost:11434"
+provider = "ollama"
+"#;
+
+    #[test]
+    fn layers() {
+        let base = write("layers.toml", BASE);
+        let local = write(
+            "layers.json",
+            r#"{ "profiles": { "dev": { "model_default": "gpt-4.1" } }, "tool_timeout": 2.5 }"#,
+        );
+        let client = loader("LAYERS")
+            .file(&base)
+            .file(&local)
+            .optional_file(base.with_file_name("missing.toml"))
+            .load()
+            .unwrap();
+        assert_eq!(client.api_base, "https://api.openai.com/v1");
+        assert_eq!(client.model_default, "gpt-4.1");
+        assert_eq!(
+            client.tool_timeout,
+            Some(std::time::Duration::from_millis(2500))
+        );
+        assert!(matches!(client.api_key, Auth::Env(var) if var == "OPENAI_API_KEY"));
+
+        let missing = loader("LAYERS").file(base.with_file_name("missing.toml"));
+        assert!(missing.load().is_err());
+        let yaml = write("layers.yaml", "model_default: gpt-4o");
+        assert!(loader("LAYERS").file(yaml).load().is_err());
+    }
+
+    #[test]
+    fn auth_layers() {
+        let base = write("auth.toml", BASE);
+        let local = write(
+            "auth.json",
+            r#"{ "api_key": { "command": ["op", "read", "op://vault/openai/key"] } }"#,
+        );
+        let client = loader("AUTH").file(&base).file(&local).load().unwrap();
+        assert!(
+            matches!(&client.api_key, Auth::Command(command, _) if command[0] == "op"),
+            "{:?}",
+            client.api_key
+        );
+
+        let profile = write(
+            "auth_profile.toml",
+            r#"
+api_key = { env = "OPENAI_API_KEY" }
+api_base = "https://api.openai.com/v1"
+model_default = "gpt-4o"
+
+[profiles.ci]
+api_key = { command = ["cat", "/run/secrets/openai"] }
+"#,
+        );
+        let client = loader("AUTH").file(&profile).profile("ci").load().unwrap();
+        assert!(matches!(&client.api_key, Auth::Command(command, _) if command[0] == "cat"));
+        let client = loader("AUTH").file(&local).file(&base).load().unwrap();
+        assert!(matches!(client.api_key, Auth::Env(var) if var == "OPENAI_API_KEY"));
+    }
+
+    #[test]
+    fn profiles() {
+        let base = write("profiles.toml", BASE);
+        let client = loader("PROFILES").file(&base).load().unwrap();
+        assert_eq!(client.model_default, "gpt-4o");
+
+        std::env::set_var("PROFILES_PROFILE", "local");
+        let client = loader("PROFILES").file(&base).load().unwrap();
+        assert_eq!(client.api_base, "http://localhost:11434");
+        assert_eq!(client.model_default, "gpt-4o-mini");
+        assert_eq!(client.provider, crate::ProviderKind::Ollama);
+
+        let client = loader("PROFILES")
+            .file(&base)
+            .profile("dev")
+            .load()
+            .unwrap();
+        assert_eq!(client.api_base, "https://api.openai.com/v1");
+        assert_eq!(client.model_default, "gpt-4o");
+        std::env::remove_var("PROFILES_PROFILE");
+
+        let err = loader("PROFILES")
+            .file(&base)
+            .profile("prod")
+            .load()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("unknown profile `prod`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn env_overrides() {
+        let base = write("env.toml", BASE);
+        std::env::set_var("OVERRIDES_API_BASE", "https://proxy.example.com/v1");
+        std::env::set_var("OVERRIDES_API_KEY", "sk-from-env");
+        std::env::set_var("OVERRIDES_MODEL", "o3");
+        let client = loader("OVERRIDES")
+            .file(&base)
+            .profile("local")
+            .load()
+            .unwrap();
+        std::env::remove_var("OVERRIDES_API_BASE");
+        std::env::remove_var("OVERRIDES_API_KEY");
+        std::env::remove_var("OVERRIDES_MODEL");
+        assert_eq!(client.api_base, "https://proxy.example.com/v1");
+        assert_eq!(client.api_key.token().unwrap().expose(), "sk-from-env");
+        assert_eq!(client.model_default, "o3");
+        assert_eq!(client.provider, crate::ProviderKind::Ollama);
+    }
+
+    #[test]
+    fn interpolation() {
+        std::env::set_var("INTERPOLATION_HOST", "gpu-box");
+        let path = write(
+            "interpolation.toml",
+            r#"
+api_base = "http://${INTERPOLATION_HOST}:${INTERPOLATION_PORT:-11434}"
+api_key = ""
+model_default = "${INTERPOLATION_MODEL:-llama3.2}"
+"#,
+        );
+        let client = loader("INTERPOLATION").file(&path).load().unwrap();
+        assert_eq!(client.api_base, "http://gpu-box:11434");
+        assert_eq!(client.model_default, "llama3.2");
+        std::env::remove_var("INTERPOLATION_HOST");
+
+        let err = loader("INTERPOLATION").file(&path).load().unwrap_err();
+        assert!(err.to_string().contains("INTERPOLATION_HOST"), "{}", err);
+
+        assert!(expand("${INTERPOLATION_HOST").is_err());
+        assert_eq!(expand("no variables").unwrap(), "no variables");
+    }
+}
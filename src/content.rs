@@ -0,0 +1,309 @@
+use std::{borrow::Cow, fmt, path::Path};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+/// Message content, either plain text or the array-of-parts form used for images,
+/// audio and files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl Content {
+    /// The text of the content, with the text parts joined by newlines.
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            Content::Text(text) => Cow::Borrowed(text),
+            Content::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Text(String::new())
+    }
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_text())
+    }
+}
+
+impl From<String> for Content {
+    fn from(value: String) -> Self {
+        Content::Text(value)
+    }
+}
+
+impl From<&str> for Content {
+    fn from(value: &str) -> Self {
+        Content::Text(value.to_string())
+    }
+}
+
+impl From<Vec<ContentPart>> for Content {
+    fn from(value: Vec<ContentPart>) -> Self {
+        Content::Parts(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+    File { file: FileData },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageUrl {
+    /// An `http(s)` URL or a base64 `data:` URI.
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ImageDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageDetail {
+    Auto,
+    Low,
+    High,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputAudio {
+    /// Base64 encoded audio.
+    pub data: String,
+    /// `wav` or `mp3`.
+    pub format: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct FileData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// A base64 `data:` URI.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: impl Into<String>, detail: Option<ImageDetail>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: url.into(),
+                detail,
+            },
+        }
+    }
+
+    /// Reads a local image and embeds it as a base64 data URI.
+    pub fn image_file(path: impl AsRef<Path>, detail: Option<ImageDetail>) -> Result<Self> {
+        let path = path.as_ref();
+        let mime = match extension(path).as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            ext => anyhow::bail!("unsupported image type `{}`: {}", ext, path.display()),
+        };
+        Ok(Self::image_url(data_uri(mime, &read(path)?), detail))
+    }
+
+    /// Reads a local `wav` or `mp3` file.
+    pub fn audio_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let format = match extension(path).as_str() {
+            ext @ ("wav" | "mp3") => ext.to_string(),
+            ext => anyhow::bail!("unsupported audio type `{}`: {}", ext, path.display()),
+        };
+        Ok(ContentPart::InputAudio {
+            input_audio: InputAudio {
+                data: STANDARD.encode(read(path)?),
+                format,
+            },
+        })
+    }
+
+    /// Reads a local file, e.g. a pdf, and embeds it inline.
+    pub fn file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mime = match extension(path).as_str() {
+            "pdf" => "application/pdf",
+            "txt" => "text/plain",
+            _ => "application/octet-stream",
+        };
+        Ok(ContentPart::File {
+            file: FileData {
+                filename: path.file_name().map(|v| v.to_string_lossy().into_owned()),
+                file_data: Some(data_uri(mime, &read(path)?)),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// References a file previously uploaded to the provider.
+    pub fn file_id(id: impl Into<String>) -> Self {
+        ContentPart::File {
+            file: FileData {
+                file_id: Some(id.into()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("read {}", path.display()))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|v| v.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn data_uri(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, STANDARD.encode(bytes))
+}
+
+/// Splits a base64 `data:` URI into its media type and payload.
+pub(crate) fn split_data_uri(uri: &str) -> Option<(&str, &str)> {
+    uri.strip_prefix("data:")?.split_once(";base64,")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{PromptMessage, Role};
+
+    fn to_json(content: impl Into<Content>) -> Value {
+        serde_json::to_value(content.into()).unwrap()
+    }
+
+    #[test]
+    fn serialize() {
+        assert_eq!(to_json("Hi"), json!("Hi"));
+        assert_eq!(
+            to_json(vec![
+                ContentPart::text("What is in it?"),
+                ContentPart::image_url("https://example.com/a.png", None),
+                ContentPart::image_url("data:image/png;base64,iVBO", Some(ImageDetail::Low)),
+                ContentPart::file_id("file-abc123"),
+            ]),
+            json!([
+                { "type": "text", "text": "What is in it?" },
+                { "type": "image_url", "image_url": { "url": "https://example.com/a.png" } },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": "data:image/png;base64,iVBO", "detail": "low" },
+                },
+                { "type": "file", "file": { "file_id": "file-abc123" } },
+            ])
+        );
+
+        let message = PromptMessage::new(Role::User, "Hi");
+        assert_eq!(serde_json::to_value(&message).unwrap()["content"], "Hi");
+    }
+
+    #[test]
+    fn deserialize() {
+        let content: Content = serde_json::from_value(json!("Hi")).unwrap();
+        assert_eq!(content, Content::Text("Hi".to_string()));
+
+        let content: Content = serde_json::from_value(json!([
+            { "type": "text", "text": "Hi" },
+            { "type": "input_audio", "input_audio": { "data": "UklG", "format": "wav" } },
+        ]))
+        .unwrap();
+        assert_eq!(
+            content,
+            Content::Parts(vec![
+                ContentPart::text("Hi"),
+                ContentPart::InputAudio {
+                    input_audio: InputAudio {
+                        data: "UklG".to_string(),
+                        format: "wav".to_string(),
+                    },
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn text() {
+        let content = Content::from(vec![
+            ContentPart::text("first"),
+            ContentPart::image_url("https://example.com/a.png", None),
+            ContentPart::text("second"),
+        ]);
+        assert_eq!(content.as_text(), "first\nsecond");
+        assert_eq!(content.to_string(), "first\nsecond");
+        assert_eq!(Content::from(vec![]).as_text(), "");
+
+        let message = PromptMessage::new(Role::User, content);
+        assert_eq!(message.text(), "first\nsecond");
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join(format!("copilot-rs-content-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("dot.PNG");
+        std::fs::write(&image, b"png").unwrap();
+
+        let part = ContentPart::image_file(&image, None).unwrap();
+        assert_eq!(
+            part,
+            ContentPart::image_url("data:image/png;base64,cG5n", None)
+        );
+        let ContentPart::ImageUrl { image_url } = &part else {
+            unreachable!()
+        };
+        assert_eq!(split_data_uri(&image_url.url), Some(("image/png", "cG5n")));
+
+        let err = ContentPart::image_file(dir.join("dot.bmp"), None).unwrap_err();
+        assert!(
+            err.to_string().starts_with("unsupported image type `bmp`"),
+            "{}",
+            err
+        );
+        assert!(ContentPart::audio_file(dir.join("missing.wav")).is_err());
+
+        let report = dir.join("report.pdf");
+        std::fs::write(&report, b"pdf").unwrap();
+        assert_eq!(
+            to_json(vec![ContentPart::file(&report).unwrap()]),
+            json!([{
+                "type": "file",
+                "file": { "filename": "report.pdf", "file_data": "data:application/pdf;base64,cGRm" },
+            }])
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
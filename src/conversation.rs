@@ -0,0 +1,350 @@
+use std::{
+    borrow::Cow,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::{
+    model_name, run_turn, AsMessages, ChatOptions, ChatOutcome, Client, Content, FunctionTool,
+    PromptMessage, Role, SummaryMemory, Tools, Usage,
+};
+
+/// A chat history that grows with every [`Conversation::send`].
+///
+/// ```no_run
+/// use copilot_rs::{Client, Conversation};
+///
+/// let client = Client::from_env()?;
+/// let mut chat = Conversation::new().system("You are a terse assistant.");
+/// chat.send(&client, "What is the capital of France?")?;
+/// let mut what_if = chat.fork();
+/// chat.send(&client, "And of Italy?")?;
+/// what_if.send(&client, "And of Spain?")?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    pub(crate) system: Option<String>,
+    pub(crate) messages: Vec<PromptMessage>,
+    /// When each message was added, in seconds since the Unix epoch.
+    pub(crate) times: Vec<u64>,
+    pub(crate) tools: Tools,
+    pub(crate) options: ChatOptions,
+    pub(crate) memory: Option<SummaryMemory>,
+    /// The model that answered the last turn.
+    pub(crate) model: Option<String>,
+    pub(crate) usage: Usage,
+    pub(crate) created_at: u64,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Conversation {
+            system: None,
+            messages: vec![],
+            times: vec![],
+            tools: Tools::new(),
+            options: ChatOptions::default(),
+            memory: None,
+            model: None,
+            usage: Usage::default(),
+            created_at: now(),
+        }
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |v| v.as_secs())
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The system prompt, always sent first and never part of [`Conversation::messages`].
+    pub fn system(mut self, prompt: impl Into<String>) -> Self {
+        self.system = Some(prompt.into());
+        self
+    }
+
+    pub fn options(mut self, options: ChatOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Summarizes older turns before sending once the history gets long.
+    pub fn memory(mut self, memory: SummaryMemory) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Lets the model call `T` in later turns.
+    pub fn tool<T: FunctionTool>(mut self) -> Self {
+        self.tools.insert(T::key(), (T::desc(), T::inject));
+        self
+    }
+
+    pub fn tools(mut self, tools: Tools) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    pub fn set_system(&mut self, prompt: Option<String>) {
+        self.system = prompt;
+    }
+
+    pub fn set_options(&mut self, options: ChatOptions) {
+        self.options = options;
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    pub fn messages(&self) -> &[PromptMessage] {
+        &self.messages
+    }
+
+    /// Tokens used by every turn of this conversation and the ones it was forked from.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// The text of the last assistant message.
+    pub fn last_reply(&self) -> Option<Cow<'_, str>> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map(PromptMessage::text)
+    }
+
+    /// Appends a message without sending it, e.g. to replay an earlier chat.
+    pub fn push(&mut self, message: PromptMessage) {
+        self.messages.push(message);
+        self.times.push(now());
+    }
+
+    /// Sends a user message and appends it with the assistant and tool messages of
+    /// the reply. Nothing is appended when the request fails.
+    pub fn send(&mut self, client: &Client, message: impl Into<Content>) -> Result<ChatOutcome> {
+        self.send_message(client, PromptMessage::new(Role::User, message))
+    }
+
+    pub fn send_message(&mut self, client: &Client, message: PromptMessage) -> Result<ChatOutcome> {
+        self.push(message);
+        let reply = self.reply(client);
+        if reply.is_err() {
+            self.messages.pop();
+            self.times.pop();
+        }
+        reply
+    }
+
+    /// Lets the model answer the history as it is and appends the reply, e.g. after
+    /// messages were added with [`Conversation::push`].
+    pub fn reply(&mut self, client: &Client) -> Result<ChatOutcome> {
+        if let Some(memory) = &self.memory {
+            let len = self.messages.len();
+            if let Some(usage) = memory.compact(client, &self.options, &mut self.messages)? {
+                self.usage += usage;
+                let summarized = len + 1 - self.messages.len();
+                self.times.splice(..summarized, [now()]);
+            }
+        }
+        let turn = run_turn(client, &self.as_messages(), &self.options, &self.tools)?;
+        self.usage += turn.usage;
+        self.model = Some(model_name(client, &self.options).to_string());
+        let outcome = turn.outcome();
+        turn.messages
+            .into_iter()
+            .for_each(|message| self.push(message));
+        Ok(outcome)
+    }
+
+    /// A copy that continues independently of this conversation.
+    pub fn fork(&self) -> Conversation {
+        self.clone()
+    }
+
+    /// A copy holding only the first `len` messages, to retry or explore from there.
+    pub fn fork_at(&self, len: usize) -> Conversation {
+        let mut fork = self.clone();
+        fork.rewind(len);
+        fork
+    }
+
+    /// Drops every message after the first `len`.
+    pub fn rewind(&mut self, len: usize) {
+        self.messages.truncate(len);
+        self.times.truncate(len);
+    }
+
+    /// Drops the last user message and everything answered after it, returning them.
+    pub fn undo(&mut self) -> Vec<PromptMessage> {
+        let start = self
+            .messages
+            .iter()
+            .rposition(|message| message.role == Role::User)
+            .unwrap_or(0);
+        self.times.truncate(start);
+        self.messages.split_off(start)
+    }
+}
+
+/// The system prompt followed by the history, as sent to the model.
+impl AsMessages for Conversation {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        match &self.system {
+            None => Cow::Borrowed(&self.messages),
+            Some(system) => Cow::Owned(
+                std::iter::once(PromptMessage::new(Role::System, system.as_str()))
+                    .chain(self.messages.iter().cloned())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{fake, FunctionImplTrait, ToolImpl};
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Get weather of an location")]
+    struct GetCurrentWeather {
+        #[props(desc = "The city and state")]
+        location: String,
+    }
+
+    impl FunctionImplTrait for GetCurrentWeather {
+        fn exec(&self) -> String {
+            format!("heavy rain in {}", self.location)
+        }
+    }
+
+    fn texts(chat: &Conversation) -> Vec<String> {
+        chat.messages()
+            .iter()
+            .map(|v| v.text().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn send() {
+        let (client, requests) = fake::serve(vec![json!("Paris."), json!("Rome.")]);
+        let mut chat = Conversation::new().system("Be terse.");
+        let outcome = chat.send(&client, "Capital of France?").unwrap();
+        assert_eq!(outcome, ChatOutcome::Message("Paris.".to_string()));
+        chat.send(&client, "And of Italy?").unwrap();
+
+        assert_eq!(
+            texts(&chat),
+            ["Capital of France?", "Paris.", "And of Italy?", "Rome."]
+        );
+        assert_eq!(chat.last_reply().unwrap(), "Rome.");
+        assert_eq!(chat.usage().total_tokens, 30);
+        assert_eq!(chat.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(chat.times.len(), 4);
+
+        // the system prompt comes first and the whole history is sent
+        let requests = requests.lock().unwrap();
+        let sent = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0], json!({ "role": "system", "content": "Be terse." }));
+        assert_eq!(sent[3]["content"], "And of Italy?");
+    }
+
+    #[test]
+    fn failed_send_changes_nothing() {
+        let (client, _) = fake::serve(vec![json!("Paris.")]);
+        let mut chat = Conversation::new();
+        chat.send(&client, "Capital of France?").unwrap();
+        let (messages, usage) = (chat.messages().to_vec(), chat.usage());
+
+        assert!(chat.send(&client, "And of Italy?").is_err());
+        assert_eq!(chat.messages(), messages);
+        assert_eq!(chat.usage(), usage);
+        assert_eq!(chat.times.len(), messages.len());
+    }
+
+    #[test]
+    fn tools() {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+            json!("It rains in Tianjin."),
+        ];
+        let (client, requests) = fake::serve(replies);
+        let mut chat = Conversation::new().tool::<GetCurrentWeather>();
+        let outcome = chat.send(&client, "Weather in Tianjin?").unwrap();
+        assert_eq!(outcome.to_string(), "It rains in Tianjin.");
+
+        let roles: Vec<_> = chat.messages().iter().map(|v| v.role.clone()).collect();
+        assert_eq!(
+            roles,
+            [Role::User, Role::Assistant, Role::Tool, Role::Assistant]
+        );
+        assert_eq!(chat.messages()[2].text(), "heavy rain in Tianjin");
+        assert_eq!(chat.messages()[2].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(chat.usage().total_tokens, 30);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0]["tools"][0]["function"]["name"],
+            "GetCurrentWeather"
+        );
+    }
+
+    #[test]
+    fn fork() {
+        let (client, _) = fake::serve(vec![json!("Paris."), json!("Rome."), json!("Madrid.")]);
+        let mut chat = Conversation::new();
+        chat.send(&client, "Capital of France?").unwrap();
+        let mut what_if = chat.fork();
+        chat.send(&client, "And of Italy?").unwrap();
+        what_if.send(&client, "And of Spain?").unwrap();
+
+        assert_eq!(texts(&chat)[2..], ["And of Italy?", "Rome."]);
+        assert_eq!(texts(&what_if)[2..], ["And of Spain?", "Madrid."]);
+        // a fork carries the usage it was made with
+        assert_eq!(what_if.usage().total_tokens, 30);
+
+        let start = chat.fork_at(2);
+        assert_eq!(texts(&start), ["Capital of France?", "Paris."]);
+        assert_eq!(chat.messages().len(), 4);
+    }
+
+    #[test]
+    fn undo_and_rewind() {
+        let mut chat = Conversation::new();
+        assert!(chat.undo().is_empty());
+        for (role, text) in [
+            (Role::User, "Capital of France?"),
+            (Role::Assistant, "Paris."),
+            (Role::User, "And of Italy?"),
+            (Role::Assistant, "Rome."),
+        ] {
+            chat.push(PromptMessage::new(role, text));
+        }
+
+        let undone = chat.undo();
+        assert_eq!(undone.len(), 2);
+        assert_eq!(undone[0].text(), "And of Italy?");
+        assert_eq!(texts(&chat), ["Capital of France?", "Paris."]);
+        assert_eq!(chat.times.len(), 2);
+
+        chat.rewind(1);
+        assert_eq!(texts(&chat), ["Capital of France?"]);
+        assert_eq!(chat.last_reply(), None);
+        chat.rewind(5);
+        assert_eq!(chat.messages().len(), 1);
+    }
+}
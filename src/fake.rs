@@ -0,0 +1,95 @@
+// a local OpenAI-compatible server for tests, answering with queued replies
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::Client;
+
+/// The request bodies the server received, in order.
+pub(crate) type Requests = Arc<Mutex<Vec<Value>>>;
+
+/// Serves one reply per request: a string is an assistant message with that text,
+/// an object a whole assistant message. Requests after the last reply get a 500.
+pub(crate) fn serve(replies: Vec<Value>) -> (Client, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_base = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Requests::default();
+    let seen = requests.clone();
+    thread::spawn(move || {
+        let mut replies = replies.into_iter();
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            let body = read_body(&stream);
+            seen.lock().unwrap().push(body);
+            let (status, body) = match replies.next() {
+                Some(message) => ("200 OK", completion(message)),
+                None => ("500 Internal Server Error", "no more replies".to_string()),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+    let client = Client::builder()
+        .api_base(api_base)
+        .api_key("test-key")
+        .model_default("gpt-4o".to_string())
+        .build();
+    (client, requests)
+}
+
+/// An assistant message calling `name` with `arguments`.
+pub(crate) fn tool_call(id: &str, name: &str, arguments: &str) -> Value {
+    json!({
+        "role": "assistant",
+        "content": null,
+        "tool_calls": [{
+            "id": id,
+            "type": "function",
+            "function": { "name": name, "arguments": arguments },
+        }],
+    })
+}
+
+fn completion(message: Value) -> String {
+    let message = match message {
+        Value::String(text) => json!({ "role": "assistant", "content": text }),
+        message => message,
+    };
+    json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "gpt-4o",
+        "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+    })
+    .to_string()
+}
+
+fn read_body(stream: &std::net::TcpStream) -> Value {
+    let mut reader = BufReader::new(stream);
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+            len = v.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0; len];
+    let _ = reader.read_exact(&mut body);
+    serde_json::from_slice(&body).unwrap_or(Value::Null)
+}
@@ -1,177 +1,435 @@
+// lets tests in this crate use the derive, which names `::copilot_rs`
+#[cfg(test)]
+extern crate self as copilot_rs;
+
+pub mod agent;
+mod approval;
+pub mod auth;
+mod background;
+mod cancel;
+mod config;
+mod content;
+mod conversation;
+#[cfg(test)]
+mod fake;
+mod memory;
+mod middleware;
+pub mod provider;
+mod repair;
+mod telemetry;
+pub mod tokenizer;
+mod transcript;
 mod types;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+pub use approval::{Approver, Decision, ToolPolicy};
+pub use auth::{Auth, Secret};
+use background::Background;
+pub use cancel::CancellationToken;
+pub use config::ConfigLoader;
+pub use content::{Content, ContentPart, FileData, ImageDetail, ImageUrl, InputAudio};
+pub use conversation::Conversation;
 pub use copilot_rs_core::*;
 pub use copilot_rs_macro::{complete, FunctionTool};
-use std::{collections::HashMap, marker::PhantomData, pin::Pin};
-use types::{ChatCompletion, OpenAIRequest, Role};
-pub use types::{Client, PromptMessage};
+pub use memory::SummaryMemory;
+pub use middleware::Middleware;
+pub use provider::{Provider, ProviderKind};
+pub use repair::repair;
+use std::{
+    borrow::{Borrow, Cow},
+    collections::{HashMap, VecDeque},
+    future::Future,
+    iter::once,
+    path::Path,
+    pin::Pin,
+};
+pub use tokenizer::{ContextBudget, Encoding, Truncation};
+pub use types::{
+    ChatCompletion, ChatOptions, ChatOutcome, Choice, Client, Delta, Function, OpenAIRequest,
+    PromptMessage, ReasoningEffort, Role, ToolCall, Usage,
+};
 pub trait Structure {}
 
 pub trait FunctionImplTrait {
     fn exec(&self) -> String;
 }
 
-type InjectionImpl = fn(std::collections::HashMap<String, serde_json::Value>) -> String;
-type FunctionName = String;
-
-struct NormalChat<T = String> {
-    _marker: PhantomData<T>,
+/// The `exec` of tools deriving `FunctionTool` with `#[props(state = "S")]`, which get
+/// the state set with [`Client::with_state`].
+pub trait FunctionImplWithContext<S> {
+    fn exec(&self, ctx: &ToolContext<S>) -> String;
 }
 
+pub type InjectionImpl =
+    fn(std::collections::HashMap<String, serde_json::Value>, &ToolContext) -> String;
+pub type FunctionName = String;
+/// The tools the model may call, with the functions answering them.
+pub type Tools = HashMap<FunctionName, (ToolImpl, InjectionImpl)>;
+
+/// What `.chat()` in a `#[complete]` function expands to. Errors and refusals both
+/// become the reply text; use `.chat_outcome()` to tell them apart.
 pub fn chat(
     model: &Client,
     messages: &[PromptMessage],
-    chat_model: &str,
-    temperature: f32,
-    max_tokens: u32,
-    functions: HashMap<FunctionName, (ToolImpl, InjectionImpl)>,
+    options: &ChatOptions,
+    functions: Tools,
 ) -> String {
-    match normal_chat(
-        model,
-        messages,
-        chat_model,
-        temperature,
-        max_tokens,
-        functions,
-    ) {
-        Ok(output) => output,
+    match messages.chat_with_tools(model, options, &functions) {
+        Ok(output) => output.to_string(),
         Err(e) => e.to_string(),
     }
 }
 
+/// What `.chat_outcome()` in a `#[complete]` function expands to: the reply with
+/// refusals kept apart, and errors left as errors.
+pub fn chat_outcome(
+    model: &Client,
+    messages: &[PromptMessage],
+    options: &ChatOptions,
+    functions: Tools,
+) -> Result<ChatOutcome> {
+    messages.chat_with_tools(model, options, &functions)
+}
+
+/// What `.async_chat()` in a `#[complete]` function expands to. Like [`chat`], it
+/// flattens errors and refusals into the reply text.
+pub fn async_chat(
+    model: &Client,
+    messages: &[PromptMessage],
+    options: &ChatOptions,
+    functions: Tools,
+) -> impl Future<Output = String> + Send + 'static {
+    let reply = messages.async_chat_with_tools(model, options, &functions);
+    async move {
+        match reply.await {
+            Ok(output) => output.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
 pub fn normal_chat(
     client: &Client,
     messages: &[PromptMessage],
-    chat_model: &str,
-    temperature: f32,
-    max_tokens: u32,
-    functions: HashMap<FunctionName, (ToolImpl, InjectionImpl)>,
-) -> Result<String> {
-    let tools: Vec<_> = functions.iter().map(|(_, (v, _))| v).collect();
-    let requst_client = reqwest::blocking::Client::new();
-    let url = format!("{}/chat/completions", client.api_base);
-    let common_builder = requst_client.post(url).bearer_auth(&client.api_key);
-
-    let chat_model = if chat_model.is_empty() {
-        &client.model_default
-    } else {
-        chat_model
+    options: &ChatOptions,
+    functions: &Tools,
+) -> Result<ChatOutcome> {
+    Ok(run_turn(client, messages, options, functions)?.outcome())
+}
+
+/// The messages the model and the tools added in answer to a prompt.
+#[derive(Debug, Clone, Default)]
+pub struct Turn {
+    pub messages: Vec<PromptMessage>,
+    pub usage: Usage,
+}
+
+impl Turn {
+    /// The final answer, `"none"` when the model returned no choices.
+    pub fn outcome(&self) -> ChatOutcome {
+        self.messages
+            .last()
+            .map(Into::into)
+            .unwrap_or(ChatOutcome::Message("none".to_string()))
+    }
+}
+
+/// Like [`normal_chat`], but returns every message that was added to the prompt.
+pub fn run_turn(
+    client: &Client,
+    messages: &[PromptMessage],
+    options: &ChatOptions,
+    functions: &Tools,
+) -> Result<Turn> {
+    let tools: Vec<_> = functions.values().map(|(v, _)| v).collect();
+
+    let mut turn = Turn::default();
+    let (message, usage) = complete(client, messages, options, tools)?;
+    turn.usage += usage;
+    let Some(common_message) = message else {
+        return Ok(turn);
+    };
+    let Some(tool_calls) = &common_message.tool_calls else {
+        turn.messages.push(common_message);
+        return Ok(turn);
     };
 
-    let json = OpenAIRequest {
+    let call = tool_calls.first().context("no tool calls")?;
+    let tool_message = call_tool(client, functions, call)?;
+    turn.messages.push(common_message);
+    turn.messages.push(tool_message);
+    let total_message = messages
+        .iter()
+        .chain(&turn.messages)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let (message, usage) = complete(client, &total_message, options, vec![])?;
+    turn.usage += usage;
+    turn.messages.push(message.context("no message")?);
+    Ok(turn)
+}
+
+/// The model a request with `options` goes to.
+pub(crate) fn model_name<'a>(client: &'a Client, options: &'a ChatOptions) -> &'a str {
+    options
+        .model
+        .as_deref()
+        .filter(|v| !v.is_empty())
+        .unwrap_or(&client.model_default)
+}
+
+/// Sends one request, fitted to the context budget, and returns the message of the
+/// first choice with what it cost.
+pub(crate) fn complete(
+    client: &Client,
+    messages: &[PromptMessage],
+    options: &ChatOptions,
+    tools: Vec<&ToolImpl>,
+) -> Result<(Option<PromptMessage>, Usage)> {
+    if client.cancellation.is_cancelled() {
+        bail!("cancelled");
+    }
+    let chat_model = model_name(client, options);
+    let messages = match &options.context_budget {
+        Some(budget) => budget.fit(chat_model, messages, &tools).into_owned(),
+        None => messages.to_vec(),
+    };
+    let mut json = OpenAIRequest {
         model: chat_model.to_string(),
-        messages: messages.to_vec(),
-        max_tokens,
-        temperature,
+        messages,
         stream: false,
         tools: (!tools.is_empty()).then_some(tools),
+        options,
     };
+    for middleware in &client.middleware {
+        middleware.before_request(&mut json)?;
+    }
+    let res = telemetry::chat(client, &json, || provider::send(client, &json))?;
+    let message = res.choices.into_iter().next().and_then(|v| v.message);
+    Ok((message, res.usage.unwrap_or_default()))
+}
+
+/// Runs the function behind a tool call, if its policy allows it, and returns its
+/// result as a tool message.
+pub(crate) fn call_tool(
+    client: &Client,
+    functions: &Tools,
+    call: &ToolCall,
+) -> Result<PromptMessage> {
+    let mut call = call.clone();
+    for middleware in &client.middleware {
+        middleware.before_tool(&mut call)?;
+    }
+    let output = telemetry::tool(client, &call, || {
+        let mut output = run_tool(client, functions, &call)?;
+        for middleware in client.middleware.iter().rev() {
+            middleware.after_tool(&call, &mut output)?;
+        }
+        Ok(output)
+    })?;
+    Ok(output.tool(call.id))
+}
+
+fn run_tool(client: &Client, functions: &Tools, call: &ToolCall) -> Result<String> {
+    let call_name = &call.function.name;
+    // mistakes of the model are told to it, so it can correct them
+    let Some((_, call_func)) = functions.get(call_name) else {
+        return Ok(format!("error: unknown tool {}", call_name));
+    };
+    let arguments = match approval::review(client, call) {
+        Ok(arguments) => arguments,
+        Err(reason) => return Ok(format!("Not run: {}", reason)),
+    };
+    let args: HashMap<String, serde_json::Value> = match client.parse_json(&arguments) {
+        Ok(args) => args,
+        Err(e) => {
+            return Ok(format!(
+                "error: invalid arguments for {}: {:#}",
+                call_name, e
+            ))
+        }
+    };
+    let output = cancel::run_tool(client, call, *call_func, args)?;
+    Ok(output.unwrap_or_else(|| format!("error: the tool {} timed out", call_name)))
+}
+
+/// Anything holding an ordered list of messages that can be sent to the model.
+pub trait AsMessages {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]>;
+}
+
+impl AsMessages for [PromptMessage] {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        Cow::Borrowed(self)
+    }
+}
 
-    let builder = common_builder
-        .try_clone()
-        .context("build request")?
-        .json(&json);
-    let res = builder.send()?.text()?;
-    let res = serde_json::from_str::<ChatCompletion>(&res)?;
-    if let Some(common_message) = res.choices.first().and_then(|v| v.message.as_ref()) {
-        if let Some(tool_calls) = &common_message.tool_calls {
-            let tool_messages = tool_calls
-                .first()
-                .map(|call| {
-                    let call_name = &call.function.name;
-                    let (_, call_func) = functions.get(call_name).unwrap();
-                    let args = &call.function.arguments;
-                    let args = args.replace("\\\"", "\"");
-                    let args: HashMap<String, serde_json::Value> =
-                        serde_json::from_str(&args).unwrap();
-                    let result = call_func(args);
-                    result.tool(call.id.clone())
-                })
-                .unwrap();
-            let tool_messages = vec![common_message.clone(), tool_messages];
-            let total_message = messages
-                .iter()
-                .chain(&tool_messages)
-                .map(|v| v.clone())
-                .collect::<Vec<_>>();
-            let json = OpenAIRequest {
-                model: client.model_default.to_string(),
-                messages: total_message,
-                max_tokens,
-                temperature,
-                stream: false,
-                tools: None,
-            };
-
-            let builder = common_builder.json(&json);
-            let res = builder.send()?.text()?;
-            let res = serde_json::from_str::<ChatCompletion>(&res)?;
-            let r = res
-                .choices
-                .first()
-                .as_ref()
-                .context("no choices")?
-                .message
-                .as_ref()
-                .context("no message")?;
-            Ok(r.content.clone())
-        } else {
-            Ok(common_message.content.clone())
+impl<const N: usize> AsMessages for [PromptMessage; N] {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl AsMessages for Vec<PromptMessage> {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl AsMessages for VecDeque<PromptMessage> {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        match self.as_slices() {
+            (front, []) => Cow::Borrowed(front),
+            _ => Cow::Owned(self.iter().cloned().collect()),
         }
-    } else {
-        Ok("none".to_string())
     }
 }
 
-pub trait Chat {
-    fn chat(&self) -> String {
-        "chat".to_string()
+impl AsMessages for dyn AsRef<[PromptMessage]> + '_ {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+impl<T: AsMessages + ?Sized> AsMessages for &T {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        (**self).as_messages()
+    }
+}
+
+impl<T: AsMessages + ?Sized> AsMessages for &mut T {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        (**self).as_messages()
     }
+}
 
-    fn async_chat(&self) -> Pin<Box<impl std::future::Future<Output = String>>> {
-        Box::pin(async { "async_chat".to_string() })
+impl<T: AsMessages + ?Sized> AsMessages for Box<T> {
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        (**self).as_messages()
     }
 }
 
-impl Chat for Vec<PromptMessage> {}
-impl Chat for dyn AsRef<[PromptMessage]> {}
+/// The messages an iterator yields, see [`IntoMessages::messages`].
+#[derive(Debug, Clone)]
+pub struct Messages<I>(I);
+
+impl<I> AsMessages for Messages<I>
+where
+    I: Iterator + Clone,
+    I::Item: Borrow<PromptMessage>,
+{
+    fn as_messages(&self) -> Cow<'_, [PromptMessage]> {
+        Cow::Owned(self.0.clone().map(|v| v.borrow().clone()).collect())
+    }
+}
+
+/// Lets `.chat()` take an iterator of messages or of references to them, e.g.
+/// `history.iter().filter(|v| v.role != Role::Tool).messages().chat()`.
+pub trait IntoMessages: Iterator + Clone + Sized
+where
+    Self::Item: Borrow<PromptMessage>,
+{
+    fn messages(self) -> Messages<Self> {
+        Messages(self)
+    }
+}
+
+impl<I> IntoMessages for I
+where
+    I: Iterator + Clone,
+    I::Item: Borrow<PromptMessage>,
+{
+}
+
+/// The reply of an async chat; it does not borrow the messages, client or tools.
+pub type ChatFuture = Pin<Box<dyn Future<Output = Result<ChatOutcome>> + Send>>;
+
+/// Sends messages to the model without the `#[complete]` macro.
+///
+/// ```no_run
+/// use copilot_rs::{Chat, ChatOptions, Client, IntoPrompt};
+///
+/// let client = Client::from_env()?;
+/// let options = ChatOptions::builder().temperature(0.2).build();
+/// let reply = vec!["Hello".user()].chat_with(&client, &options)?;
+/// # anyhow::Ok(())
+/// ```
+pub trait Chat: AsMessages {
+    fn chat_with(&self, client: &Client, options: &ChatOptions) -> Result<ChatOutcome> {
+        self.chat_with_tools(client, options, &Tools::new())
+    }
+
+    /// Like [`Chat::chat_with`], answering the model's tool calls with `tools`.
+    fn chat_with_tools(
+        &self,
+        client: &Client,
+        options: &ChatOptions,
+        tools: &Tools,
+    ) -> Result<ChatOutcome> {
+        normal_chat(client, &self.as_messages(), options, tools)
+    }
+
+    fn async_chat_with(&self, client: &Client, options: &ChatOptions) -> ChatFuture {
+        self.async_chat_with_tools(client, options, &Tools::new())
+    }
+
+    /// Runs [`Chat::chat_with_tools`] on a background thread, so it can be awaited
+    /// from any executor.
+    fn async_chat_with_tools(
+        &self,
+        client: &Client,
+        options: &ChatOptions,
+        tools: &Tools,
+    ) -> ChatFuture {
+        let client = client.clone();
+        let messages = self.as_messages().into_owned();
+        let options = options.clone();
+        let tools = tools.clone();
+        Box::pin(Background::spawn(move || {
+            normal_chat(&client, &messages, &options, &tools)
+        }))
+    }
+}
+
+impl<T: AsMessages + ?Sized> Chat for T {}
 
 pub trait IntoPrompt
 where
     Self: ToString,
 {
     fn system(&self) -> PromptMessage {
-        PromptMessage {
-            role: Role::System,
-            content: self.to_string(),
-            tool_calls: None,
-            tool_call_id: None,
-        }
+        PromptMessage::new(Role::System, self.to_string())
     }
     fn user(&self) -> PromptMessage {
-        PromptMessage {
-            role: Role::User,
-            content: self.to_string(),
-            tool_calls: None,
-            tool_call_id: None,
-        }
+        PromptMessage::new(Role::User, self.to_string())
     }
     fn assistant(&self) -> PromptMessage {
-        PromptMessage {
-            role: Role::Assistant,
-            content: self.to_string(),
-            tool_calls: None,
-            tool_call_id: None,
-        }
+        PromptMessage::new(Role::Assistant, self.to_string())
     }
     fn tool(&self, id: String) -> PromptMessage {
         PromptMessage {
-            role: Role::Tool,
-            content: self.to_string(),
-            tool_calls: None,
             tool_call_id: Some(id),
+            ..PromptMessage::new(Role::Tool, self.to_string())
         }
     }
+    /// A user message with the text followed by the given content parts.
+    fn user_with_parts(&self, parts: Vec<ContentPart>) -> PromptMessage {
+        let text = ContentPart::text(self.to_string());
+        PromptMessage::new(Role::User, once(text).chain(parts).collect::<Vec<_>>())
+    }
+    /// A user message with a local image, encoded as a base64 data URI.
+    fn user_with_image(&self, path: impl AsRef<Path>) -> Result<PromptMessage> {
+        Ok(self.user_with_parts(vec![ContentPart::image_file(path, None)?]))
+    }
+    fn user_with_image_url(&self, url: &str) -> PromptMessage {
+        self.user_with_parts(vec![ContentPart::image_url(url, None)])
+    }
+    fn user_with_audio(&self, path: impl AsRef<Path>) -> Result<PromptMessage> {
+        Ok(self.user_with_parts(vec![ContentPart::audio_file(path)?]))
+    }
+    fn user_with_file(&self, path: impl AsRef<Path>) -> Result<PromptMessage> {
+        Ok(self.user_with_parts(vec![ContentPart::file(path)?]))
+    }
 }
 
 impl IntoPrompt for &str {}
@@ -0,0 +1,267 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model_name, run_turn, tokenizer::groups, ChatOptions, Client, Encoding, PromptMessage, Role,
+    Tools, Usage,
+};
+
+const DEFAULT_PROMPT: &str = "Summarize the conversation below for your own future reference. \
+Keep names, facts, decisions, open questions and the results of tool calls. \
+Answer with the summary only.";
+const SUMMARY_HEADER: &str = "Summary of the earlier conversation:";
+
+/// Replaces older turns with a summary written by the model once the history
+/// grows past `max_tokens`.
+///
+/// The summary becomes a system message in front of the turns that are kept, and
+/// is itself summarized again the next time. A tool call is never separated from
+/// its results.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummaryMemory {
+    pub max_tokens: usize,
+    /// Tokens of the most recent turns that are kept verbatim.
+    pub keep_tokens: usize,
+    /// The instructions given to the model when summarizing.
+    pub prompt: String,
+    /// Defaults to the encoding of the model.
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
+}
+
+impl SummaryMemory {
+    /// Summarizes when the history exceeds `max_tokens`, keeping about half of it.
+    pub fn new(max_tokens: usize) -> Self {
+        SummaryMemory {
+            max_tokens,
+            keep_tokens: max_tokens / 2,
+            prompt: DEFAULT_PROMPT.to_string(),
+            encoding: None,
+        }
+    }
+
+    pub fn keep_tokens(mut self, keep_tokens: usize) -> Self {
+        self.keep_tokens = keep_tokens;
+        self
+    }
+
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Summarizes the older part of `messages` in place if they are over budget and
+    /// returns what the summary cost, `None` if nothing was summarized. `messages` is
+    /// left untouched on error.
+    pub fn compact(
+        &self,
+        client: &Client,
+        options: &ChatOptions,
+        messages: &mut Vec<PromptMessage>,
+    ) -> Result<Option<Usage>> {
+        let encoding = self
+            .encoding
+            .unwrap_or_else(|| Encoding::for_model(model_name(client, options)));
+        if encoding.count_messages(messages) <= self.max_tokens {
+            return Ok(None);
+        }
+
+        let groups = groups(messages);
+        let mut kept = 0;
+        let mut tokens = 0;
+        for group in groups.iter().rev() {
+            let group_tokens: usize = group.iter().map(|m| encoding.count_message(m)).sum();
+            if kept > 0 && tokens + group_tokens > self.keep_tokens {
+                break;
+            }
+            tokens += group_tokens;
+            kept += group.len();
+        }
+        let cut = messages.len() - kept;
+        if cut == 0 {
+            return Ok(None);
+        }
+
+        let prompt = [
+            PromptMessage::new(Role::System, self.prompt.as_str()),
+            PromptMessage::new(Role::User, transcript(&messages[..cut])),
+        ];
+        let options = ChatOptions {
+            context_budget: None,
+            ..options.clone()
+        };
+        let turn = run_turn(client, &prompt, &options, &Tools::new())?;
+        let outcome = turn.outcome();
+        if outcome.is_refusal() {
+            bail!("summary refused: {}", outcome);
+        }
+
+        let summary = PromptMessage::new(
+            Role::System,
+            format!("{}\n{}", SUMMARY_HEADER, outcome.into_text()),
+        );
+        messages.splice(..cut, [summary]);
+        Ok(Some(turn.usage))
+    }
+}
+
+/// Renders messages as plain text, so tool calls can be summarized without tools.
+fn transcript(messages: &[PromptMessage]) -> String {
+    let mut out = String::new();
+    for message in messages {
+        let role = match message.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool result",
+        };
+        let text = message.text();
+        if !text.is_empty() {
+            out.push_str(&format!("{}: {}\n", role, text));
+        }
+        for call in message.tool_calls.iter().flatten() {
+            out.push_str(&format!(
+                "{} called {}({})\n",
+                role, call.function.name, call.function.arguments
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{fake, Conversation, Function, IntoPrompt, ToolCall};
+
+    fn history() -> Vec<PromptMessage> {
+        let call = |id: &str, location: &str| ToolCall {
+            id: id.to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: "GetCurrentWeather".to_string(),
+                arguments: format!(r#"{{"location":"{}"}}"#, location),
+            },
+        };
+        vec![
+            "You are a weather bot.".system(),
+            "What is the weather in Tianjin and Beijing?".user(),
+            PromptMessage {
+                content: None,
+                tool_calls: Some(vec![call("call_1", "Tianjin"), call("call_2", "Beijing")]),
+                ..PromptMessage::new(Role::Assistant, "")
+            },
+            "heavy rain".tool("call_1".to_string()),
+            "sunny".tool("call_2".to_string()),
+            "Tianjin has heavy rain, Beijing is sunny.".assistant(),
+            "And tomorrow?".user(),
+        ]
+    }
+
+    fn memory(max_tokens: usize) -> SummaryMemory {
+        SummaryMemory::new(max_tokens).encoding(Encoding::O200kBase)
+    }
+
+    #[test]
+    fn under_budget() {
+        let (client, requests) = fake::serve(vec![]);
+        let mut messages = history();
+        let usage = memory(100_000)
+            .compact(&client, &ChatOptions::default(), &mut messages)
+            .unwrap();
+        assert_eq!(usage, None);
+        assert_eq!(messages, history());
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn replaces_a_prefix() {
+        let before = history();
+        // around the tokens of each suffix, including those ending inside the
+        // tool call and its results
+        let mut tokens = 0;
+        let mut budgets = vec![0];
+        for message in before.iter().rev() {
+            tokens += Encoding::O200kBase.count_message(message);
+            budgets.extend([tokens - 1, tokens, tokens + 1]);
+        }
+        let (client, requests) = fake::serve(vec![json!("the summary"); budgets.len()]);
+        for keep_tokens in budgets {
+            let mut messages = before.clone();
+            let usage = memory(0)
+                .keep_tokens(keep_tokens)
+                .compact(&client, &ChatOptions::default(), &mut messages)
+                .unwrap();
+            let Some(usage) = usage else {
+                assert_eq!(messages, before, "{}", keep_tokens);
+                continue;
+            };
+            assert_eq!(usage.total_tokens, 15);
+
+            // what `Conversation::reply` relies on
+            let cut = before.len() + 1 - messages.len();
+            assert_eq!(messages[1..], before[cut..], "{}", keep_tokens);
+            assert_eq!(messages[0].role, Role::System);
+            assert_eq!(
+                messages[0].text(),
+                format!("{}\nthe summary", SUMMARY_HEADER)
+            );
+            assert_ne!(
+                messages[1].role,
+                Role::Tool,
+                "{}: {:?}",
+                keep_tokens,
+                messages
+            );
+            assert_eq!(messages.last(), before.last());
+
+            let request = requests.lock().unwrap().pop().unwrap();
+            let transcript = request["messages"][1]["content"].as_str().unwrap();
+            assert_eq!(transcript, super::transcript(&before[..cut]));
+        }
+    }
+
+    #[test]
+    fn failures_leave_messages() {
+        let mut messages = history();
+        let (client, _) = fake::serve(vec![]);
+        assert!(memory(0)
+            .compact(&client, &ChatOptions::default(), &mut messages)
+            .is_err());
+        assert_eq!(messages, history());
+
+        let refusal = json!({ "role": "assistant", "content": null, "refusal": "no" });
+        let (client, _) = fake::serve(vec![refusal]);
+        let err = memory(0)
+            .compact(&client, &ChatOptions::default(), &mut messages)
+            .unwrap_err();
+        assert!(err.to_string().contains("refused"), "{}", err);
+        assert_eq!(messages, history());
+    }
+
+    #[test]
+    fn conversation() {
+        let (client, requests) = fake::serve(vec![json!("the summary"), json!("Rain again.")]);
+        let mut conversation = Conversation::new().memory(memory(0).keep_tokens(0));
+        history()
+            .into_iter()
+            .for_each(|message| conversation.push(message));
+        conversation.reply(&client).unwrap();
+
+        let messages = conversation.messages();
+        assert_eq!(conversation.times.len(), messages.len());
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].text().starts_with(SUMMARY_HEADER));
+        assert_eq!(messages[1], history()[6]);
+        assert_eq!(messages[2].text(), "Rain again.");
+        assert_eq!(conversation.usage().total_tokens, 30);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::{OpenAIRequest, ToolCall};
+
+/// Hooks around every request and tool call of a client, e.g. to redact personal
+/// data, guard against prompt injection, audit or count.
+///
+/// Middleware runs in the order it was added before a request or tool call and in
+/// reverse order after it. An error from any hook fails the chat.
+///
+/// ```no_run
+/// use anyhow::Result;
+/// use copilot_rs::{Client, Middleware, OpenAIRequest};
+///
+/// struct RedactEmails;
+///
+/// impl Middleware for RedactEmails {
+///     fn before_request(&self, request: &mut OpenAIRequest) -> Result<()> {
+///         for message in &mut request.messages {
+///             if let Some(text) = message.content.as_ref().map(|v| v.as_text().into_owned()) {
+///                 message.content = Some(text.replace("alice@example.com", "[email]").into());
+///             }
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let client = Client::from_env()?.with_middleware(RedactEmails);
+/// # anyhow::Ok(())
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Before the request is sent; it may be changed.
+    fn before_request(&self, request: &mut OpenAIRequest) -> Result<()> {
+        let _ = request;
+        Ok(())
+    }
+
+    /// After a response arrived, with its HTTP status and the body as sent by the
+    /// provider, before it is parsed. The body may be changed.
+    fn after_response(&self, status: u16, body: &mut String) -> Result<()> {
+        let _ = (status, body);
+        Ok(())
+    }
+
+    /// Before a tool runs; the call, including its arguments, may be changed.
+    fn before_tool(&self, call: &mut ToolCall) -> Result<()> {
+        let _ = call;
+        Ok(())
+    }
+
+    /// After a tool ran, or was rejected or timed out, with what the model will see
+    /// as its result.
+    fn after_tool(&self, call: &ToolCall, output: &mut String) -> Result<()> {
+        let _ = (call, output);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Middleware")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::bail;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        fake, run_turn, ChatOptions, FunctionImplTrait, FunctionTool, PromptMessage, Role,
+        ToolImpl, Tools,
+    };
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Get weather of an location")]
+    struct GetCurrentWeather {
+        #[props(desc = "The city and state")]
+        location: String,
+    }
+
+    impl FunctionImplTrait for GetCurrentWeather {
+        fn exec(&self) -> String {
+            format!("heavy rain in {}", self.location)
+        }
+    }
+
+    fn tools() -> Tools {
+        Tools::from([(
+            GetCurrentWeather::key(),
+            (GetCurrentWeather::desc(), GetCurrentWeather::inject as _),
+        )])
+    }
+
+    fn ask(client: &crate::Client) -> Result<crate::Turn> {
+        let messages = [PromptMessage::new(
+            Role::User,
+            "What is the weather in Tianjin?",
+        )];
+        run_turn(client, &messages, &ChatOptions::default(), &tools())
+    }
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct Record(&'static str, Log);
+
+    impl Record {
+        fn log(&self, hook: &str) {
+            self.1.lock().unwrap().push(format!("{} {}", self.0, hook));
+        }
+    }
+
+    impl Middleware for Record {
+        fn before_request(&self, _: &mut OpenAIRequest) -> Result<()> {
+            self.log("before_request");
+            Ok(())
+        }
+
+        fn after_response(&self, _: u16, _: &mut String) -> Result<()> {
+            self.log("after_response");
+            Ok(())
+        }
+
+        fn before_tool(&self, _: &mut ToolCall) -> Result<()> {
+            self.log("before_tool");
+            Ok(())
+        }
+
+        fn after_tool(&self, _: &ToolCall, _: &mut String) -> Result<()> {
+            self.log("after_tool");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn order() {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+            json!("It rains."),
+        ];
+        let (client, _) = fake::serve(replies);
+        let log = Log::default();
+        let client = client
+            .with_middleware(Record("a", log.clone()))
+            .with_middleware(Record("b", log.clone()));
+        ask(&client).unwrap();
+
+        let request = [
+            "a before_request",
+            "b before_request",
+            "b after_response",
+            "a after_response",
+        ];
+        let tool = [
+            "a before_tool",
+            "b before_tool",
+            "b after_tool",
+            "a after_tool",
+        ];
+        let expected: Vec<_> = [&request[..], &tool, &request].concat();
+        assert_eq!(*log.lock().unwrap(), expected);
+    }
+
+    struct Rewrite;
+
+    impl Middleware for Rewrite {
+        fn before_request(&self, request: &mut OpenAIRequest) -> Result<()> {
+            for message in &mut request.messages {
+                let text = message.text().replace("Tianjin", "[city]");
+                message.content = Some(text.into());
+            }
+            Ok(())
+        }
+
+        fn after_response(&self, _: u16, body: &mut String) -> Result<()> {
+            *body = body.replace("It rains.", "It pours.");
+            Ok(())
+        }
+
+        fn before_tool(&self, call: &mut ToolCall) -> Result<()> {
+            call.function.arguments = r#"{"location": "Beijing"}"#.to_string();
+            Ok(())
+        }
+
+        fn after_tool(&self, _: &ToolCall, output: &mut String) -> Result<()> {
+            output.push_str(" (checked)");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hooks_change_what_they_see() {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+            json!("It rains."),
+        ];
+        let (client, requests) = fake::serve(replies);
+        let turn = ask(&client.with_middleware(Rewrite)).unwrap();
+
+        assert_eq!(turn.messages[1].text(), "heavy rain in Beijing (checked)");
+        assert_eq!(turn.outcome().to_string(), "It pours.");
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0]["messages"][0]["content"],
+            "What is the weather in [city]?"
+        );
+        assert_eq!(
+            requests[1]["messages"][2]["content"],
+            "heavy rain in Beijing (checked)"
+        );
+    }
+
+    struct Fail(&'static str);
+
+    impl Middleware for Fail {
+        fn before_request(&self, _: &mut OpenAIRequest) -> Result<()> {
+            match self.0 {
+                "before_request" => bail!("blocked request"),
+                _ => Ok(()),
+            }
+        }
+
+        fn after_response(&self, _: u16, _: &mut String) -> Result<()> {
+            match self.0 {
+                "after_response" => bail!("blocked response"),
+                _ => Ok(()),
+            }
+        }
+
+        fn after_tool(&self, _: &ToolCall, _: &mut String) -> Result<()> {
+            match self.0 {
+                "after_tool" => bail!("blocked tool"),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn errors_fail_the_chat() {
+        for (hook, message, sent) in [
+            ("before_request", "blocked request", 0),
+            ("after_response", "blocked response", 1),
+            ("after_tool", "blocked tool", 1),
+        ] {
+            let replies = vec![
+                fake::tool_call("call_1", "GetCurrentWeather", r#"{"location": "Tianjin"}"#),
+                json!("It rains."),
+            ];
+            let (client, requests) = fake::serve(replies);
+            let err = ask(&client.with_middleware(Fail(hook))).unwrap_err();
+            assert_eq!(format!("{:#}", err), message, "{}", hook);
+            assert_eq!(requests.lock().unwrap().len(), sent, "{}", hook);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{bail, Result};
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::{arguments_value, insert_some, Provider};
+use crate::{
+    auth::Secret, content::split_data_uri, types::OpenAIRequest, ChatCompletion, Choice, Client,
+    Content, ContentPart, Function, PromptMessage, Role, ToolCall, ToolImpl, Usage,
+};
+
+const API_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// The Anthropic Messages API, with tool calls as `tool_use`/`tool_result` blocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Anthropic;
+
+impl Provider for Anthropic {
+    fn url(&self, client: &Client, _model: &str) -> String {
+        format!("{}/messages", client.api_base)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder {
+        builder
+            .header("x-api-key", token.expose())
+            .header("anthropic-version", API_VERSION)
+    }
+
+    fn request_body(&self, client: &Client, request: &OpenAIRequest) -> Result<Value> {
+        let mut system = vec![];
+        let mut messages: Vec<(&str, Vec<Value>)> = vec![];
+        for message in &request.messages {
+            let (role, blocks) = match message.role {
+                Role::System => {
+                    system.push(message.text().into_owned());
+                    continue;
+                }
+                Role::User => ("user", content_blocks(message.content.as_ref())?),
+                Role::Assistant => {
+                    let mut blocks = content_blocks(message.content.as_ref())?;
+                    for call in message.tool_calls.iter().flatten() {
+                        blocks.push(json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.function.name,
+                            "input": arguments_value(client, call)?,
+                        }));
+                    }
+                    ("assistant", blocks)
+                }
+                Role::Tool => (
+                    "user",
+                    vec![json!({
+                        "type": "tool_result",
+                        "tool_use_id": message.tool_call_id,
+                        "content": message.text(),
+                    })],
+                ),
+            };
+            // roles must alternate, so consecutive messages of one role are merged
+            match messages.last_mut() {
+                Some((last, content)) if *last == role => content.extend(blocks),
+                _ => messages.push((role, blocks)),
+            }
+        }
+
+        let options = request.options;
+        let mut body = Map::new();
+        body.insert("model".into(), json!(request.model));
+        body.insert(
+            "messages".into(),
+            messages
+                .into_iter()
+                .map(|(role, content)| json!({ "role": role, "content": content }))
+                .collect(),
+        );
+        body.insert(
+            "max_tokens".into(),
+            json!(options
+                .max_tokens
+                .or(options.max_completion_tokens)
+                .unwrap_or(DEFAULT_MAX_TOKENS)),
+        );
+        insert_some(
+            &mut body,
+            "system",
+            (!system.is_empty()).then(|| system.join("\n\n")),
+        )?;
+        insert_some(
+            &mut body,
+            "tools",
+            request.tools.as_ref().map(|tools| {
+                tools
+                    .iter()
+                    .map(|tool| match tool {
+                        ToolImpl::Function {
+                            name,
+                            description,
+                            parameters,
+                        } => json!({
+                            "name": name,
+                            "description": description,
+                            "input_schema": parameters,
+                        }),
+                    })
+                    .collect::<Vec<_>>()
+            }),
+        )?;
+        insert_some(&mut body, "temperature", options.temperature)?;
+        insert_some(&mut body, "top_p", options.top_p)?;
+        insert_some(&mut body, "stop_sequences", options.stop.as_ref())?;
+        insert_some(
+            &mut body,
+            "metadata",
+            options.user.as_ref().map(|user| json!({ "user_id": user })),
+        )?;
+        Ok(Value::Object(body))
+    }
+
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion> {
+        let res: Response = serde_json::from_str(body)?;
+        let mut text = vec![];
+        let mut tool_calls = vec![];
+        for block in res.content {
+            match block {
+                Block::Text { text: t } => text.push(t),
+                Block::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                    id,
+                    ty: "function".to_string(),
+                    function: Function {
+                        name,
+                        arguments: input.to_string(),
+                    },
+                }),
+                Block::Other => {}
+            }
+        }
+        let text = text.join("");
+        let refused = res.stop_reason.as_deref() == Some("refusal");
+        let message = PromptMessage {
+            content: (!text.is_empty() && !refused).then(|| Content::Text(text.clone())),
+            refusal: refused.then_some(text),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            ..PromptMessage::new(Role::Assistant, "")
+        };
+        let finish_reason = res.stop_reason.map(|reason| {
+            match reason.as_str() {
+                "end_turn" | "stop_sequence" => "stop",
+                "tool_use" => "tool_calls",
+                "max_tokens" => "length",
+                "refusal" => "content_filter",
+                other => other,
+            }
+            .to_string()
+        });
+        Ok(ChatCompletion {
+            choices: vec![Choice {
+                message: Some(message),
+                finish_reason,
+                ..Default::default()
+            }],
+            id: res.id,
+            model: res.model,
+            object: "chat.completion".to_string(),
+            usage: res.usage.map(|usage| Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+                total_tokens: usage.input_tokens + usage.output_tokens,
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+fn content_blocks(content: Option<&Content>) -> Result<Vec<Value>> {
+    let parts = match content {
+        None => return Ok(vec![]),
+        Some(Content::Text(text)) if text.is_empty() => return Ok(vec![]),
+        Some(Content::Text(text)) => return Ok(vec![json!({ "type": "text", "text": text })]),
+        Some(Content::Parts(parts)) => parts,
+    };
+    parts
+        .iter()
+        .map(|part| {
+            Ok(match part {
+                ContentPart::Text { text } => json!({ "type": "text", "text": text }),
+                ContentPart::ImageUrl { image_url } => json!({
+                    "type": "image",
+                    "source": source(&image_url.url),
+                }),
+                ContentPart::File { file } => match (&file.file_data, &file.file_id) {
+                    (Some(data), _) => json!({ "type": "document", "source": source(data) }),
+                    (None, Some(id)) => json!({
+                        "type": "document",
+                        "source": { "type": "file", "file_id": id },
+                    }),
+                    (None, None) => bail!("file part without data or id"),
+                },
+                ContentPart::InputAudio { .. } => bail!("anthropic does not accept audio input"),
+            })
+        })
+        .collect()
+}
+
+fn source(url: &str) -> Value {
+    match split_data_uri(url) {
+        Some((media_type, data)) => json!({
+            "type": "base64",
+            "media_type": media_type,
+            "data": data,
+        }),
+        None => json!({ "type": "url", "url": url }),
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: String,
+    model: String,
+    content: Vec<Block>,
+    stop_reason: Option<String>,
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ResponseUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
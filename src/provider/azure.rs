@@ -0,0 +1,64 @@
+use anyhow::Result;
+use reqwest::blocking::RequestBuilder;
+use serde::{Deserialize, Serialize};
+
+use super::{OpenAI, Provider};
+use crate::{auth::Secret, types::OpenAIRequest, ChatCompletion, Client};
+
+const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI, addressed by deployment rather than by model.
+///
+/// `Client::api_base` is the resource endpoint, e.g. `https://my-resource.openai.azure.com`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Azure {
+    /// The deployment to call; defaults to the requested model name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    #[serde(default = "default_api_version")]
+    pub api_version: String,
+    /// Send `Client::api_key` as a Microsoft Entra ID bearer token instead of an `api-key` header.
+    #[serde(default)]
+    pub entra: bool,
+}
+
+fn default_api_version() -> String {
+    DEFAULT_API_VERSION.to_string()
+}
+
+impl Default for Azure {
+    fn default() -> Self {
+        Azure {
+            deployment: None,
+            api_version: default_api_version(),
+            entra: false,
+        }
+    }
+}
+
+impl Provider for Azure {
+    fn url(&self, client: &Client, model: &str) -> String {
+        format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            client.api_base.trim_end_matches('/'),
+            self.deployment.as_deref().unwrap_or(model),
+            self.api_version
+        )
+    }
+
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder {
+        if self.entra {
+            builder.bearer_auth(token.expose())
+        } else {
+            builder.header("api-key", token.expose())
+        }
+    }
+
+    fn request_body(&self, client: &Client, request: &OpenAIRequest) -> Result<serde_json::Value> {
+        OpenAI.request_body(client, request)
+    }
+
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion> {
+        OpenAI.parse_response(body)
+    }
+}
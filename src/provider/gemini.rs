@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::{arguments_value, call_id, insert_some, Provider};
+use crate::{
+    auth::Secret, content::split_data_uri, types::OpenAIRequest, ChatCompletion, Choice, Client,
+    Content, ContentPart, Function, PromptMessage, Role, ToolCall, ToolImpl, Usage,
+};
+
+/// The Gemini `generateContent` API, with tools as `functionDeclarations`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gemini;
+
+impl Provider for Gemini {
+    fn url(&self, client: &Client, model: &str) -> String {
+        format!("{}/models/{}:generateContent", client.api_base, model)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder {
+        builder.header("x-goog-api-key", token.expose())
+    }
+
+    fn request_body(&self, client: &Client, request: &OpenAIRequest) -> Result<Value> {
+        // function responses are matched by name, not by call id
+        let call_names: HashMap<&str, &str> = request
+            .messages
+            .iter()
+            .flat_map(|message| message.tool_calls.iter().flatten())
+            .map(|call| (call.id.as_str(), call.function.name.as_str()))
+            .collect();
+
+        let mut system = vec![];
+        let mut contents: Vec<(&str, Vec<Value>)> = vec![];
+        for message in &request.messages {
+            let (role, parts) = match message.role {
+                Role::System => {
+                    system.push(json!({ "text": message.text() }));
+                    continue;
+                }
+                Role::User => ("user", parts(message.content.as_ref())?),
+                Role::Assistant => {
+                    let mut parts = parts(message.content.as_ref())?;
+                    for call in message.tool_calls.iter().flatten() {
+                        parts.push(json!({
+                            "functionCall": {
+                                "name": call.function.name,
+                                "args": arguments_value(client, call)?,
+                            }
+                        }));
+                    }
+                    ("model", parts)
+                }
+                Role::Tool => {
+                    let id = message.tool_call_id.as_deref().unwrap_or_default();
+                    let text = message.text();
+                    let response = match serde_json::from_str::<Value>(&text) {
+                        Ok(value @ Value::Object(_)) => value,
+                        _ => json!({ "result": text }),
+                    };
+                    (
+                        "user",
+                        vec![json!({
+                            "functionResponse": {
+                                "name": call_names.get(id).copied().unwrap_or(id),
+                                "response": response,
+                            }
+                        })],
+                    )
+                }
+            };
+            match contents.last_mut() {
+                Some((last, existing)) if *last == role => existing.extend(parts),
+                _ => contents.push((role, parts)),
+            }
+        }
+
+        let options = request.options;
+        let mut config = Map::new();
+        insert_some(&mut config, "temperature", options.temperature)?;
+        insert_some(&mut config, "topP", options.top_p)?;
+        insert_some(
+            &mut config,
+            "maxOutputTokens",
+            options.max_tokens.or(options.max_completion_tokens),
+        )?;
+        insert_some(&mut config, "stopSequences", options.stop.as_ref())?;
+        insert_some(&mut config, "seed", options.seed)?;
+        insert_some(&mut config, "candidateCount", options.n)?;
+        insert_some(&mut config, "presencePenalty", options.presence_penalty)?;
+        insert_some(&mut config, "frequencyPenalty", options.frequency_penalty)?;
+        insert_some(&mut config, "responseLogprobs", options.logprobs)?;
+        insert_some(&mut config, "logprobs", options.top_logprobs)?;
+
+        let mut body = Map::new();
+        body.insert(
+            "contents".into(),
+            contents
+                .into_iter()
+                .map(|(role, parts)| json!({ "role": role, "parts": parts }))
+                .collect(),
+        );
+        insert_some(
+            &mut body,
+            "systemInstruction",
+            (!system.is_empty()).then(|| json!({ "parts": system })),
+        )?;
+        insert_some(
+            &mut body,
+            "tools",
+            request.tools.as_ref().map(|tools| {
+                let declarations = tools
+                    .iter()
+                    .map(|tool| match tool {
+                        ToolImpl::Function {
+                            name,
+                            description,
+                            parameters,
+                        } => json!({
+                            "name": name,
+                            "description": description,
+                            "parameters": parameters,
+                        }),
+                    })
+                    .collect::<Vec<_>>();
+                json!([{ "functionDeclarations": declarations }])
+            }),
+        )?;
+        insert_some(
+            &mut body,
+            "generationConfig",
+            (!config.is_empty()).then_some(config),
+        )?;
+        Ok(Value::Object(body))
+    }
+
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion> {
+        let res: Response = serde_json::from_str(body)?;
+        let choices = res
+            .candidates
+            .into_iter()
+            .enumerate()
+            .map(|(index, candidate)| {
+                let mut text = vec![];
+                let mut tool_calls = vec![];
+                for part in candidate.content.map(|v| v.parts).unwrap_or_default() {
+                    if let Some(t) = part.text {
+                        text.push(t);
+                    }
+                    if let Some(call) = part.function_call {
+                        tool_calls.push(ToolCall {
+                            id: call.id.unwrap_or_else(call_id),
+                            ty: "function".to_string(),
+                            function: Function {
+                                name: call.name,
+                                arguments: call.args.to_string(),
+                            },
+                        });
+                    }
+                }
+                let text = text.join("");
+                let refused = matches!(
+                    candidate.finish_reason.as_deref(),
+                    Some("SAFETY" | "RECITATION" | "PROHIBITED_CONTENT" | "BLOCKLIST" | "SPII")
+                );
+                let message = PromptMessage {
+                    content: (!refused).then(|| Content::Text(text.clone())),
+                    refusal: refused.then(|| {
+                        format!(
+                            "blocked: {}",
+                            candidate.finish_reason.as_deref().unwrap_or_default()
+                        )
+                    }),
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    ..PromptMessage::new(Role::Assistant, "")
+                };
+                let finish_reason = if message.tool_calls.is_some() {
+                    Some("tool_calls".to_string())
+                } else {
+                    candidate.finish_reason.map(|reason| {
+                        match reason.as_str() {
+                            "STOP" => "stop",
+                            "MAX_TOKENS" => "length",
+                            _ if refused => "content_filter",
+                            other => other,
+                        }
+                        .to_string()
+                    })
+                };
+                Choice {
+                    message: Some(message),
+                    finish_reason,
+                    index: index as u32,
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+        if choices.is_empty() {
+            bail!("no candidates");
+        }
+        Ok(ChatCompletion {
+            choices,
+            id: res.response_id.unwrap_or_default(),
+            model: res.model_version.unwrap_or_default(),
+            object: "chat.completion".to_string(),
+            usage: res.usage_metadata.map(|usage| Usage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens: usage.candidates_token_count,
+                total_tokens: usage.total_token_count,
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+fn parts(content: Option<&Content>) -> Result<Vec<Value>> {
+    let parts = match content {
+        None => return Ok(vec![]),
+        Some(Content::Text(text)) if text.is_empty() => return Ok(vec![]),
+        Some(Content::Text(text)) => return Ok(vec![json!({ "text": text })]),
+        Some(Content::Parts(parts)) => parts,
+    };
+    parts
+        .iter()
+        .map(|part| {
+            Ok(match part {
+                ContentPart::Text { text } => json!({ "text": text }),
+                ContentPart::ImageUrl { image_url } => data_part(&image_url.url),
+                ContentPart::InputAudio { input_audio } => json!({
+                    "inlineData": {
+                        "mimeType": format!("audio/{}", input_audio.format),
+                        "data": input_audio.data,
+                    }
+                }),
+                ContentPart::File { file } => match (&file.file_data, &file.file_id) {
+                    (Some(data), _) => data_part(data),
+                    (None, Some(_)) => bail!("gemini only accepts files as data"),
+                    (None, None) => bail!("file part without data or id"),
+                },
+            })
+        })
+        .collect()
+}
+
+fn data_part(url: &str) -> Value {
+    match split_data_uri(url) {
+        Some((mime_type, data)) => json!({
+            "inlineData": { "mimeType": mime_type, "data": data }
+        }),
+        None => json!({ "fileData": { "fileUri": url } }),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    usage_metadata: Option<UsageMetadata>,
+    model_version: Option<String>,
+    response_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<CandidateContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    text: Option<String>,
+    function_call: Option<FunctionCall>,
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+    #[serde(default)]
+    total_token_count: u32,
+}
@@ -0,0 +1,138 @@
+mod anthropic;
+mod azure;
+mod gemini;
+mod ollama;
+mod openai;
+
+use std::{fmt, sync::Arc};
+
+use anyhow::{Context, Result};
+use reqwest::blocking::RequestBuilder;
+use serde::{Deserialize, Serialize};
+
+pub use anthropic::Anthropic;
+pub use azure::Azure;
+pub use gemini::Gemini;
+pub use ollama::Ollama;
+pub use openai::OpenAI;
+
+use crate::{auth::Secret, types::OpenAIRequest, ChatCompletion, Client, ToolCall};
+
+/// Translates our OpenAI-shaped request and response types to and from a vendor's
+/// wire format.
+pub trait Provider: Send + Sync {
+    /// The endpoint a completion request for `model` is posted to.
+    fn url(&self, client: &Client, model: &str) -> String;
+
+    /// Attaches the resolved credential of the client to the request.
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder;
+
+    /// Builds the vendor request body.
+    fn request_body(&self, client: &Client, request: &OpenAIRequest) -> Result<serde_json::Value>;
+
+    /// Parses a vendor response body into a `ChatCompletion`.
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion>;
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    Anthropic,
+    Gemini,
+    Ollama,
+    Azure(Azure),
+    /// A wire format of your own. Set in code only: a client using it cannot be
+    /// serialized.
+    #[serde(skip)]
+    Custom(Arc<dyn Provider>),
+}
+
+impl ProviderKind {
+    pub fn as_provider(&self) -> &dyn Provider {
+        match self {
+            ProviderKind::OpenAI => &OpenAI,
+            ProviderKind::Anthropic => &Anthropic,
+            ProviderKind::Gemini => &Gemini,
+            ProviderKind::Ollama => &Ollama,
+            ProviderKind::Azure(azure) => azure,
+            ProviderKind::Custom(provider) => provider.as_ref(),
+        }
+    }
+}
+
+impl fmt::Debug for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderKind::OpenAI => f.write_str("OpenAI"),
+            ProviderKind::Anthropic => f.write_str("Anthropic"),
+            ProviderKind::Gemini => f.write_str("Gemini"),
+            ProviderKind::Ollama => f.write_str("Ollama"),
+            ProviderKind::Azure(azure) => f.debug_tuple("Azure").field(azure).finish(),
+            ProviderKind::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Custom providers are only equal to themselves.
+impl PartialEq for ProviderKind {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ProviderKind::Azure(a), ProviderKind::Azure(b)) => a == b,
+            (ProviderKind::Custom(a), ProviderKind::Custom(b)) => Arc::ptr_eq(a, b),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Eq for ProviderKind {}
+
+/// Sends `request` through the provider configured on `client`.
+pub(crate) fn send(client: &Client, request: &OpenAIRequest) -> Result<ChatCompletion> {
+    let provider = client.provider.as_provider();
+    let body = provider.request_body(client, request)?;
+    let builder = reqwest::blocking::Client::new().post(provider.url(client, &request.model));
+    let token = client.api_key.token()?;
+    let res = provider.authorize(builder, &token).json(&body).send()?;
+    let status = res.status();
+    let mut text = res.text()?;
+    for middleware in client.middleware.iter().rev() {
+        middleware.after_response(status.as_u16(), &mut text)?;
+    }
+    if !status.is_success() {
+        anyhow::bail!("{}: {}", status, text);
+    }
+    provider
+        .parse_response(&text)
+        .with_context(|| format!("parse response: {}", text))
+}
+
+/// Parses tool call arguments, which vendors other than OpenAI send as objects,
+/// repairing them if the client does.
+fn arguments_value(client: &Client, call: &ToolCall) -> Result<serde_json::Value> {
+    match client.parse_json(&call.function.arguments) {
+        Ok(value @ serde_json::Value::Object(_)) => Ok(value),
+        Ok(_) => anyhow::bail!("arguments of tool call {} are not an object", call.id),
+        Err(e) => Err(e).with_context(|| format!("arguments of tool call {}", call.id)),
+    }
+}
+
+/// An id for the tool calls of vendors that send none. Random, so the calls of
+/// different turns, or of a saved conversation, never share one.
+fn call_id() -> String {
+    format!("call_{:016x}", fastrand::u64(..))
+}
+
+/// Inserts `value` into `map` unless it is `None`.
+fn insert_some<T: Serialize>(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: Option<T>,
+) -> Result<()> {
+    if let Some(value) = value {
+        map.insert(key.to_string(), serde_json::to_value(value)?);
+    }
+    Ok(())
+}
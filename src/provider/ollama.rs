@@ -0,0 +1,193 @@
+use anyhow::{bail, Result};
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::{arguments_value, call_id, insert_some, Provider};
+use crate::{
+    auth::Secret, content::split_data_uri, types::OpenAIRequest, ChatCompletion, Choice, Client,
+    Content, ContentPart, Function, PromptMessage, Role, ToolCall, Usage,
+};
+
+/// Ollama's native `/api/chat` API.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ollama;
+
+impl Provider for Ollama {
+    fn url(&self, client: &Client, _model: &str) -> String {
+        format!("{}/api/chat", client.api_base)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder {
+        if token.expose().is_empty() {
+            builder
+        } else {
+            builder.bearer_auth(token.expose())
+        }
+    }
+
+    fn request_body(&self, client: &Client, request: &OpenAIRequest) -> Result<Value> {
+        let messages = request
+            .messages
+            .iter()
+            .map(|v| message(client, v))
+            .collect::<Result<Vec<_>>>()?;
+
+        let options = request.options;
+        let mut sampling = Map::new();
+        insert_some(&mut sampling, "temperature", options.temperature)?;
+        insert_some(&mut sampling, "top_p", options.top_p)?;
+        insert_some(
+            &mut sampling,
+            "num_predict",
+            options.max_tokens.or(options.max_completion_tokens),
+        )?;
+        insert_some(&mut sampling, "stop", options.stop.as_ref())?;
+        insert_some(&mut sampling, "seed", options.seed)?;
+        insert_some(&mut sampling, "presence_penalty", options.presence_penalty)?;
+        insert_some(
+            &mut sampling,
+            "frequency_penalty",
+            options.frequency_penalty,
+        )?;
+
+        let mut body = Map::new();
+        body.insert("model".into(), json!(request.model));
+        body.insert("messages".into(), json!(messages));
+        body.insert("stream".into(), json!(false));
+        insert_some(&mut body, "tools", request.tools.as_ref())?;
+        insert_some(
+            &mut body,
+            "options",
+            (!sampling.is_empty()).then_some(sampling),
+        )?;
+        Ok(Value::Object(body))
+    }
+
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion> {
+        let res: Response = serde_json::from_str(body)?;
+        let tool_calls = res
+            .message
+            .tool_calls
+            .into_iter()
+            .map(|call| ToolCall {
+                id: call_id(),
+                ty: "function".to_string(),
+                function: Function {
+                    name: call.function.name,
+                    arguments: call.function.arguments.to_string(),
+                },
+            })
+            .collect::<Vec<_>>();
+        let finish_reason = if tool_calls.is_empty() {
+            res.done_reason
+        } else {
+            Some("tool_calls".to_string())
+        };
+        let message = PromptMessage {
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            ..PromptMessage::new(Role::Assistant, res.message.content)
+        };
+        let usage = match (res.prompt_eval_count, res.eval_count) {
+            (None, None) => None,
+            (prompt, completion) => {
+                let (prompt, completion) = (prompt.unwrap_or(0), completion.unwrap_or(0));
+                Some(Usage {
+                    prompt_tokens: prompt,
+                    completion_tokens: completion,
+                    total_tokens: prompt + completion,
+                })
+            }
+        };
+        Ok(ChatCompletion {
+            choices: vec![Choice {
+                message: Some(message),
+                finish_reason,
+                ..Default::default()
+            }],
+            model: res.model,
+            object: "chat.completion".to_string(),
+            usage,
+            ..Default::default()
+        })
+    }
+}
+
+fn message(client: &Client, message: &PromptMessage) -> Result<Value> {
+    let role = match message.role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool => "tool",
+    };
+    let mut text = vec![];
+    let mut images = vec![];
+    match &message.content {
+        None => {}
+        Some(Content::Text(t)) => text.push(t.as_str()),
+        Some(Content::Parts(parts)) => {
+            for part in parts {
+                match part {
+                    ContentPart::Text { text: t } => text.push(t),
+                    ContentPart::ImageUrl { image_url } => match split_data_uri(&image_url.url) {
+                        Some((_, data)) => images.push(data),
+                        None => bail!("ollama only accepts inline images"),
+                    },
+                    _ => bail!("ollama only accepts text and image content"),
+                }
+            }
+        }
+    }
+
+    let mut value = Map::new();
+    value.insert("role".into(), json!(role));
+    value.insert("content".into(), json!(text.join("\n")));
+    insert_some(&mut value, "images", (!images.is_empty()).then_some(images))?;
+    let tool_calls = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| {
+            Ok(json!({
+                "function": {
+                    "name": call.function.name,
+                    "arguments": arguments_value(client, call)?,
+                }
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    insert_some(
+        &mut value,
+        "tool_calls",
+        message.tool_calls.is_some().then_some(tool_calls),
+    )?;
+    Ok(Value::Object(value))
+}
+
+#[derive(Deserialize)]
+struct Response {
+    model: String,
+    message: ResponseMessage,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<ResponseToolCall>,
+}
+
+#[derive(Deserialize)]
+struct ResponseToolCall {
+    function: ResponseFunction,
+}
+
+#[derive(Deserialize)]
+struct ResponseFunction {
+    name: String,
+    arguments: Value,
+}
@@ -0,0 +1,27 @@
+use anyhow::Result;
+use reqwest::blocking::RequestBuilder;
+
+use super::Provider;
+use crate::{auth::Secret, types::OpenAIRequest, ChatCompletion, Client};
+
+/// The OpenAI `/chat/completions` API, also spoken by most compatible servers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenAI;
+
+impl Provider for OpenAI {
+    fn url(&self, client: &Client, _model: &str) -> String {
+        format!("{}/chat/completions", client.api_base)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, token: &Secret) -> RequestBuilder {
+        builder.bearer_auth(token.expose())
+    }
+
+    fn request_body(&self, _client: &Client, request: &OpenAIRequest) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(request)?)
+    }
+
+    fn parse_response(&self, body: &str) -> Result<ChatCompletion> {
+        Ok(serde_json::from_str(body)?)
+    }
+}
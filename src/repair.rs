@@ -0,0 +1,230 @@
+use std::borrow::Cow;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
+/// Parses `input` as JSON, falling back to [`repair`] when the strict parse fails
+/// and `lenient` is enabled.
+pub fn from_str<T: DeserializeOwned>(input: &str, lenient: bool) -> Result<T> {
+    match serde_json::from_str(input) {
+        Ok(value) => Ok(value),
+        Err(e) if !lenient => Err(e).context("parse json"),
+        Err(_) => {
+            let repaired = repair(input);
+            serde_json::from_str(&repaired)
+                .with_context(|| format!("parse repaired json: {}", repaired))
+        }
+    }
+}
+
+/// Best-effort repair of the JSON models tend to produce: markdown fences, trailing
+/// commas, single-quoted strings, bare keys, raw control characters inside strings,
+/// Python literals and truncated output.
+///
+/// Valid JSON is returned untouched.
+pub fn repair(input: &str) -> Cow<'_, str> {
+    if serde_json::from_str::<serde_json::Value>(input).is_ok() {
+        return Cow::Borrowed(input);
+    }
+    Cow::Owned(Repairer::default().run(strip_fences(input)))
+}
+
+fn strip_fences(input: &str) -> &str {
+    let trimmed = input.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    let body = rest.split_once('\n').map(|(_, body)| body).unwrap_or("");
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+#[derive(Default)]
+struct Repairer {
+    out: String,
+    stack: Vec<char>,
+    /// An object key was written that has no `:` yet.
+    dangling_key: bool,
+}
+
+impl Repairer {
+    fn run(mut self, input: &str) -> String {
+        let start = input.find(['{', '[']).unwrap_or(0);
+        let mut chars = input[start..].chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => {
+                    self.dangling_key = self.at_key();
+                    self.string(c, &mut chars);
+                }
+                '{' | '[' => {
+                    self.stack.push(if c == '{' { '}' } else { ']' });
+                    self.out.push(c);
+                }
+                '}' | ']' => {
+                    self.trim_dangling();
+                    self.close_key();
+                    if self.stack.last() == Some(&c) {
+                        self.stack.pop();
+                        self.out.push(c);
+                    }
+                    if self.stack.is_empty() {
+                        break;
+                    }
+                }
+                ',' | ':' => {
+                    self.dangling_key = false;
+                    self.out.push(c);
+                }
+                c if c.is_whitespace() => self.out.push(c),
+                c => {
+                    let mut word = String::from(c);
+                    while let Some(&next) = chars.peek() {
+                        if next.is_alphanumeric() || matches!(next, '_' | '-' | '+' | '.') {
+                            word.push(next);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    self.dangling_key = self.at_key();
+                    self.bare_word(&word);
+                }
+            }
+        }
+
+        self.trim_dangling();
+        self.close_key();
+        if self.out.trim_end().ends_with(':') {
+            self.out.push_str("null");
+        }
+        while let Some(close) = self.stack.pop() {
+            self.out.push(close);
+        }
+        self.out
+    }
+
+    fn string(&mut self, quote: char, chars: &mut std::iter::Peekable<std::str::Chars>) {
+        self.out.push('"');
+        while let Some(c) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.out.push('"');
+                    return;
+                }
+                '\\' => match chars.next() {
+                    Some(e @ ('"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't')) => {
+                        self.out.push('\\');
+                        self.out.push(e);
+                    }
+                    Some('u') => {
+                        let hex: String = chars.clone().take(4).collect();
+                        if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                            self.out.push_str("\\u");
+                            self.out.push_str(&hex);
+                            chars.nth(3);
+                        } else {
+                            // a literal backslash, as in a Windows path
+                            self.out.push_str("\\\\u");
+                        }
+                    }
+                    Some('\'') => self.out.push('\''),
+                    Some(other) => {
+                        self.out.push_str("\\\\");
+                        self.escape(other);
+                    }
+                    None => break,
+                },
+                c => self.escape(c),
+            }
+        }
+        // truncated inside a string
+        self.out.push('"');
+    }
+
+    fn escape(&mut self, c: char) {
+        match c {
+            '"' => self.out.push_str("\\\""),
+            '\n' => self.out.push_str("\\n"),
+            '\r' => self.out.push_str("\\r"),
+            '\t' => self.out.push_str("\\t"),
+            c if c.is_control() => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => self.out.push(c),
+        }
+    }
+
+    fn bare_word(&mut self, word: &str) {
+        match word {
+            "true" | "True" => self.out.push_str("true"),
+            "false" | "False" => self.out.push_str("false"),
+            "null" | "None" | "undefined" => self.out.push_str("null"),
+            w if serde_json::from_str::<serde_json::Number>(w).is_ok() => self.out.push_str(w),
+            w => {
+                self.out.push('"');
+                w.chars().for_each(|c| self.escape(c));
+                self.out.push('"');
+            }
+        }
+    }
+
+    /// Whether the next string is an object key.
+    fn at_key(&self) -> bool {
+        self.stack.last() == Some(&'}')
+            && matches!(self.out.trim_end().chars().last(), Some('{' | ','))
+    }
+
+    /// Gives `null` to a key the input ended or the object closed after.
+    fn close_key(&mut self) {
+        if self.dangling_key {
+            self.out.push_str(":null");
+            self.dangling_key = false;
+        }
+    }
+
+    fn trim_dangling(&mut self) {
+        let trimmed = self.out.trim_end().trim_end_matches(',').len();
+        self.out.truncate(trimmed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::repair;
+
+    #[test]
+    fn repairs() {
+        let cases = [
+            (r#"{"a": 1}"#, json!({"a": 1})),
+            (r#"{"a": 1,}"#, json!({"a": 1})),
+            (r#"[1, 2, ]"#, json!([1, 2])),
+            (r#"{'a': 'it\'s'}"#, json!({"a": "it's"})),
+            (r#"{a: 1, b_c: "x"}"#, json!({"a": 1, "b_c": "x"})),
+            ("```json\n{\"a\": 1}\n```", json!({"a": 1})),
+            ("Here you go: {\"a\": 1} hope it helps", json!({"a": 1})),
+            ("{\"a\": \"line\none\"}", json!({"a": "line\none"})),
+            (
+                r#"{"a": True, "b": None, "c": False}"#,
+                json!({"a": true, "b": null, "c": false}),
+            ),
+            (r#"{"a": [1, {"b": "tru"#, json!({"a": [1, {"b": "tru"}]})),
+            (r#"{"a": "#, json!({"a": null})),
+            (r#"{"a": 1, "b"#, json!({"a": 1, "b": null})),
+            (r#"{"a": 1, "b""#, json!({"a": 1, "b": null})),
+            (r#"{"a": {"b": 1, "c"#, json!({"a": {"b": 1, "c": null}})),
+            (r#"{"a": 1, "b"}"#, json!({"a": 1, "b": null})),
+            (
+                r#"{"path": "C:\users\dan"}"#,
+                json!({"path": "C:\\users\\dan"}),
+            ),
+            (r#"{"a": "\u00e9\u12"}"#, json!({"a": "é\\u12"})),
+        ];
+        for (input, expected) in cases {
+            let repaired = repair(input);
+            let value: Value = serde_json::from_str(&repaired)
+                .unwrap_or_else(|e| panic!("{} -> {}: {}", input, repaired, e));
+            assert_eq!(value, expected, "{}", input);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// spans following the OpenTelemetry GenAI semantic conventions; without the
+// `tracing` feature every function here only runs its closure
+
+use anyhow::Result;
+
+use crate::{ChatCompletion, Client, OpenAIRequest, ToolCall};
+
+#[cfg(feature = "tracing")]
+const CAPTURE_CONTENT_ENV: &str = "OTEL_INSTRUMENTATION_GENAI_CAPTURE_MESSAGE_CONTENT";
+
+/// Runs `send` in a `chat {model}` span.
+#[cfg(feature = "tracing")]
+pub(crate) fn chat(
+    client: &Client,
+    request: &OpenAIRequest,
+    send: impl FnOnce() -> Result<ChatCompletion>,
+) -> Result<ChatCompletion> {
+    use tracing::field::Empty;
+
+    let options = request.options;
+    let url = reqwest::Url::parse(&client.api_base).ok();
+    let span = tracing::info_span!(
+        "chat",
+        otel.name = %format!("chat {}", request.model),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        otel.status_description = Empty,
+        gen_ai.operation.name = "chat",
+        gen_ai.provider.name = provider_name(client),
+        gen_ai.request.model = %request.model,
+        gen_ai.request.temperature = options.temperature.map(f64::from),
+        gen_ai.request.top_p = options.top_p.map(f64::from),
+        gen_ai.request.max_tokens = options.max_completion_tokens.or(options.max_tokens),
+        gen_ai.request.seed = options.seed,
+        gen_ai.request.frequency_penalty = options.frequency_penalty.map(f64::from),
+        gen_ai.request.presence_penalty = options.presence_penalty.map(f64::from),
+        gen_ai.request.stop_sequences = options.stop.as_ref().map(|v| format!("{:?}", v)),
+        gen_ai.request.choice.count = options.n,
+        gen_ai.response.id = Empty,
+        gen_ai.response.model = Empty,
+        gen_ai.response.finish_reasons = Empty,
+        gen_ai.usage.input_tokens = Empty,
+        gen_ai.usage.output_tokens = Empty,
+        gen_ai.input.messages = Empty,
+        gen_ai.output.messages = Empty,
+        server.address = url.as_ref().and_then(|v| v.host_str()),
+        server.port = url.as_ref().and_then(|v| v.port_or_known_default()),
+        error.type = Empty,
+    );
+    if capture_content(client) {
+        span.record(
+            "gen_ai.input.messages",
+            serde_json::to_string(&request.messages).unwrap_or_default(),
+        );
+    }
+
+    let res = span.in_scope(send);
+    match &res {
+        Ok(res) => {
+            let finish_reasons: Vec<_> = res
+                .choices
+                .iter()
+                .filter_map(|v| v.finish_reason.as_deref())
+                .collect();
+            span.record("gen_ai.response.id", res.id.as_str());
+            span.record("gen_ai.response.model", res.model.as_str());
+            span.record(
+                "gen_ai.response.finish_reasons",
+                format!("{:?}", finish_reasons),
+            );
+            if let Some(usage) = &res.usage {
+                span.record("gen_ai.usage.input_tokens", usage.prompt_tokens);
+                span.record("gen_ai.usage.output_tokens", usage.completion_tokens);
+            }
+            if capture_content(client) {
+                let messages: Vec<_> = res
+                    .choices
+                    .iter()
+                    .filter_map(|v| v.message.as_ref())
+                    .collect();
+                span.record(
+                    "gen_ai.output.messages",
+                    serde_json::to_string(&messages).unwrap_or_default(),
+                );
+            }
+        }
+        Err(e) => failed(&span, e),
+    }
+    res
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn chat(
+    _client: &Client,
+    _request: &OpenAIRequest,
+    send: impl FnOnce() -> Result<ChatCompletion>,
+) -> Result<ChatCompletion> {
+    send()
+}
+
+/// Runs `run` in an `execute_tool {name}` span.
+#[cfg(feature = "tracing")]
+pub(crate) fn tool(
+    client: &Client,
+    call: &ToolCall,
+    run: impl FnOnce() -> Result<String>,
+) -> Result<String> {
+    use tracing::field::Empty;
+
+    let span = tracing::info_span!(
+        "execute_tool",
+        otel.name = %format!("execute_tool {}", call.function.name),
+        otel.kind = "internal",
+        otel.status_code = Empty,
+        otel.status_description = Empty,
+        gen_ai.operation.name = "execute_tool",
+        gen_ai.tool.name = %call.function.name,
+        gen_ai.tool.call.id = %call.id,
+        gen_ai.tool.type = "function",
+        gen_ai.tool.call.arguments = Empty,
+        gen_ai.tool.call.result = Empty,
+        error.type = Empty,
+    );
+    if capture_content(client) {
+        span.record(
+            "gen_ai.tool.call.arguments",
+            call.function.arguments.as_str(),
+        );
+    }
+
+    let output = span.in_scope(run);
+    match &output {
+        Ok(output) if capture_content(client) => {
+            span.record("gen_ai.tool.call.result", output.as_str());
+        }
+        Ok(_) => {}
+        Err(e) => failed(&span, e),
+    }
+    output
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn tool(
+    _client: &Client,
+    _call: &ToolCall,
+    run: impl FnOnce() -> Result<String>,
+) -> Result<String> {
+    run()
+}
+
+#[cfg(feature = "tracing")]
+fn failed(span: &tracing::Span, e: &anyhow::Error) {
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_description", e.to_string());
+    span.record("error.type", "_OTHER");
+}
+
+/// Prompts, completions and tool arguments and results are only recorded when asked
+/// for, as they may hold personal data.
+#[cfg(feature = "tracing")]
+fn capture_content(client: &Client) -> bool {
+    client.capture_content
+        || std::env::var(CAPTURE_CONTENT_ENV).is_ok_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+#[cfg(feature = "tracing")]
+fn provider_name(client: &Client) -> &'static str {
+    use crate::ProviderKind;
+
+    match client.provider {
+        ProviderKind::OpenAI => "openai",
+        ProviderKind::Anthropic => "anthropic",
+        ProviderKind::Gemini => "gcp.gemini",
+        ProviderKind::Ollama => "ollama",
+        ProviderKind::Azure(_) => "azure.ai.openai",
+        ProviderKind::Custom(_) => "_OTHER",
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use crate::{
+        fake, run_turn, ChatOptions, FunctionImplTrait, FunctionTool, PromptMessage, Role,
+        ToolImpl, Tools,
+    };
+
+    type Fields = HashMap<String, String>;
+
+    /// Keeps the name and fields of every span.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut fields = Fields::new();
+            span.record(&mut Visitor(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((span.metadata().name(), fields));
+            span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut Visitor(&mut spans[span.into_u64() as usize - 1].1));
+        }
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[derive(copilot_rs_macro::FunctionTool, Deserialize, Serialize)]
+    #[props(desc = "Get weather of an location")]
+    struct GetCurrentWeather {
+        #[props(desc = "The city and state")]
+        location: String,
+    }
+
+    impl FunctionImplTrait for GetCurrentWeather {
+        fn exec(&self) -> String {
+            format!("heavy rain in {}", self.location)
+        }
+    }
+
+    fn spans(capture_content: bool) -> Vec<(&'static str, Fields)> {
+        let replies = vec![
+            fake::tool_call("call_1", "GetCurrentWeather", r#"{"location":"Tianjin"}"#),
+            json!("It rains."),
+        ];
+        let (mut client, _) = fake::serve(replies);
+        client.capture_content = capture_content;
+        let tools = Tools::from([(
+            GetCurrentWeather::key(),
+            (GetCurrentWeather::desc(), GetCurrentWeather::inject as _),
+        )]);
+        let messages = [PromptMessage::new(Role::User, "Weather in Tianjin?")];
+
+        let capture = Capture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            run_turn(&client, &messages, &ChatOptions::default(), &tools).unwrap();
+        });
+        let spans = capture.0.lock().unwrap().clone();
+        spans
+    }
+
+    #[test]
+    fn attributes() {
+        let spans = spans(false);
+        let names: Vec<_> = spans.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["chat", "execute_tool", "chat"]);
+
+        let chat = &spans[0].1;
+        assert_eq!(chat["otel.name"], "chat gpt-4o");
+        assert_eq!(chat["gen_ai.operation.name"], "chat");
+        assert_eq!(chat["gen_ai.provider.name"], "openai");
+        assert_eq!(chat["gen_ai.request.model"], "gpt-4o");
+        assert_eq!(chat["gen_ai.response.id"], "chatcmpl-test");
+        assert_eq!(chat["gen_ai.response.finish_reasons"], r#"["stop"]"#);
+        assert_eq!(chat["gen_ai.usage.input_tokens"], "10");
+        assert_eq!(chat["gen_ai.usage.output_tokens"], "5");
+
+        let tool = &spans[1].1;
+        assert_eq!(tool["otel.name"], "execute_tool GetCurrentWeather");
+        assert_eq!(tool["gen_ai.operation.name"], "execute_tool");
+        assert_eq!(tool["gen_ai.tool.name"], "GetCurrentWeather");
+        assert_eq!(tool["gen_ai.tool.call.id"], "call_1");
+
+        // content stays out of the spans unless asked for
+        let content = [
+            "gen_ai.input.messages",
+            "gen_ai.output.messages",
+            "gen_ai.tool.call.arguments",
+            "gen_ai.tool.call.result",
+        ];
+        for (_, fields) in &spans {
+            for key in content {
+                assert!(!fields.contains_key(key), "{}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn content() {
+        let spans = spans(true);
+        let chat = &spans[0].1;
+        assert!(chat["gen_ai.input.messages"].contains("Weather in Tianjin?"));
+        assert!(chat["gen_ai.output.messages"].contains("GetCurrentWeather"));
+
+        let tool = &spans[1].1;
+        assert_eq!(
+            tool["gen_ai.tool.call.arguments"],
+            r#"{"location":"Tianjin"}"#
+        );
+        assert_eq!(tool["gen_ai.tool.call.result"], "heavy rain in Tianjin");
+        assert!(spans[2].1["gen_ai.output.messages"].contains("It rains."));
+    }
+}
@@ -0,0 +1,370 @@
+use std::borrow::Cow;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::{tokenizer::Tokenizer, CoreBPE};
+
+use crate::{Content, ContentPart, PromptMessage, Role, ToolImpl};
+
+static CL100K_BASE: Lazy<CoreBPE> =
+    Lazy::new(|| tiktoken_rs::cl100k_base().expect("bundled cl100k_base vocabulary"));
+static O200K_BASE: Lazy<CoreBPE> =
+    Lazy::new(|| tiktoken_rs::o200k_base().expect("bundled o200k_base vocabulary"));
+
+// every message is framed as <|start|>{role}\n{content}<|end|>\n
+const TOKENS_PER_MESSAGE: usize = 3;
+// every reply is primed with <|start|>assistant<|message|>
+const TOKENS_PER_REPLY: usize = 3;
+// a low detail image; high detail ones cost more, so this is a lower bound
+const TOKENS_PER_IMAGE: usize = 85;
+
+/// A BPE vocabulary bundled with the crate, so counting works offline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// GPT-4 and GPT-3.5.
+    Cl100kBase,
+    /// GPT-4o, o1 and later.
+    #[default]
+    O200kBase,
+}
+
+impl Encoding {
+    /// The encoding OpenAI uses for `model`, `o200k_base` for models it does not know.
+    pub fn for_model(model: &str) -> Encoding {
+        match tiktoken_rs::tokenizer::get_tokenizer(model) {
+            Some(Tokenizer::Cl100kBase) => Encoding::Cl100kBase,
+            _ => Encoding::O200kBase,
+        }
+    }
+
+    fn bpe(&self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100kBase => &CL100K_BASE,
+            Encoding::O200kBase => &O200K_BASE,
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe().encode_with_special_tokens(text).len()
+    }
+
+    /// Tokens of one message, including role, name, tool calls and framing.
+    pub fn count_message(&self, message: &PromptMessage) -> usize {
+        let role = match message.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        };
+        let mut tokens = TOKENS_PER_MESSAGE + self.count(role);
+        tokens += match &message.content {
+            None => 0,
+            Some(Content::Text(text)) => self.count(text),
+            Some(Content::Parts(parts)) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => self.count(text),
+                    ContentPart::ImageUrl { .. } => TOKENS_PER_IMAGE,
+                    _ => 0,
+                })
+                .sum(),
+        };
+        tokens += message.refusal.as_deref().map_or(0, |v| self.count(v));
+        tokens += message.name.as_deref().map_or(0, |v| self.count(v) + 1);
+        tokens += message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| self.count(&call.function.name) + self.count(&call.function.arguments))
+            .sum::<usize>();
+        tokens
+    }
+
+    /// Tokens of a prompt, including the priming of the reply.
+    pub fn count_messages(&self, messages: &[PromptMessage]) -> usize {
+        messages
+            .iter()
+            .map(|message| self.count_message(message))
+            .sum::<usize>()
+            + TOKENS_PER_REPLY
+    }
+
+    /// An estimate for tool schemas, which providers render into the prompt in their
+    /// own undocumented format.
+    pub fn count_tools(&self, tools: &[&ToolImpl]) -> usize {
+        if tools.is_empty() {
+            return 0;
+        }
+        self.count(&serde_json::to_string(tools).unwrap_or_default())
+    }
+}
+
+/// Which messages to drop when a prompt does not fit its [`ContextBudget`].
+///
+/// Leading system messages are always kept, and an assistant message with tool calls
+/// is dropped together with its tool results, since providers reject either alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// Drop the oldest messages until the prompt fits.
+    #[default]
+    DropOldest,
+    /// Keep at most the last `n` messages, then drop the oldest of those if needed.
+    KeepSystemAndLastN(usize),
+}
+
+/// The number of tokens a prompt, including tool schemas, may use.
+///
+/// Set it to the context window of the model minus the room left for the reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextBudget {
+    pub max_tokens: usize,
+    #[serde(default)]
+    pub truncation: Truncation,
+    /// Defaults to the encoding of the model.
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
+}
+
+impl ContextBudget {
+    pub fn new(max_tokens: usize) -> Self {
+        ContextBudget {
+            max_tokens,
+            truncation: Truncation::default(),
+            encoding: None,
+        }
+    }
+
+    pub fn truncation(mut self, truncation: Truncation) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// The messages that fit the budget. The last message is always kept, even
+    /// when it alone is over budget.
+    pub fn fit<'a>(
+        &self,
+        model: &str,
+        messages: &'a [PromptMessage],
+        tools: &[&ToolImpl],
+    ) -> Cow<'a, [PromptMessage]> {
+        let encoding = self.encoding.unwrap_or_else(|| Encoding::for_model(model));
+        let system = messages
+            .iter()
+            .take_while(|message| message.role == Role::System)
+            .count();
+        let (system, history) = messages.split_at(system);
+
+        let groups = groups(history);
+
+        let mut first = 0;
+        if let Truncation::KeepSystemAndLastN(n) = self.truncation {
+            let mut kept = 0;
+            first = groups.len();
+            while first > 0 && kept + groups[first - 1].len() <= n {
+                first -= 1;
+                kept += groups[first].len();
+            }
+            first = first.min(groups.len().saturating_sub(1));
+        }
+
+        let fixed = TOKENS_PER_REPLY
+            + encoding.count_tools(tools)
+            + system
+                .iter()
+                .map(|message| encoding.count_message(message))
+                .sum::<usize>();
+        let counts: Vec<usize> = groups
+            .iter()
+            .map(|group| group.iter().map(|m| encoding.count_message(m)).sum())
+            .collect();
+        let mut total = fixed + counts[first..].iter().sum::<usize>();
+        while total > self.max_tokens && first + 1 < groups.len() {
+            total -= counts[first];
+            first += 1;
+        }
+
+        if first == 0 {
+            return Cow::Borrowed(messages);
+        }
+        Cow::Owned(
+            system
+                .iter()
+                .chain(groups[first..].iter().copied().flatten())
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Splits messages into runs that must be kept or dropped together: a message and the
+/// tool results that follow it.
+pub(crate) fn groups(messages: &[PromptMessage]) -> Vec<&[PromptMessage]> {
+    let mut groups = vec![];
+    let mut start = 0;
+    for (i, message) in messages.iter().enumerate().skip(1) {
+        if message.role != Role::Tool {
+            groups.push(&messages[start..i]);
+            start = i;
+        }
+    }
+    if start < messages.len() {
+        groups.push(&messages[start..]);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Function, IntoPrompt, ToolCall};
+
+    fn calls(ids: &[&str]) -> PromptMessage {
+        let calls = ids
+            .iter()
+            .map(|id| ToolCall {
+                id: id.to_string(),
+                ty: "function".to_string(),
+                function: Function {
+                    name: "GetCurrentWeather".to_string(),
+                    arguments: r#"{"location":"Tianjin"}"#.to_string(),
+                },
+            })
+            .collect();
+        PromptMessage {
+            content: None,
+            tool_calls: Some(calls),
+            ..PromptMessage::new(Role::Assistant, "")
+        }
+    }
+
+    fn conversation() -> Vec<PromptMessage> {
+        vec![
+            "You are a weather bot.".system(),
+            "Answer in Chinese.".system(),
+            "What is the weather in Tianjin and Beijing?".user(),
+            calls(&["call_1", "call_2"]),
+            "heavy rain".tool("call_1".to_string()),
+            "sunny".tool("call_2".to_string()),
+            "Tianjin has heavy rain, Beijing is sunny.".assistant(),
+            "And tomorrow?".user(),
+        ]
+    }
+
+    fn fit(budget: ContextBudget, messages: &[PromptMessage]) -> Vec<PromptMessage> {
+        budget
+            .encoding(Encoding::O200kBase)
+            .fit("gpt-4o", messages, &[])
+            .into_owned()
+    }
+
+    /// Every tool call is answered and every tool result follows its call.
+    fn assert_paired(messages: &[PromptMessage]) {
+        let mut open = vec![];
+        for message in messages {
+            match message.role {
+                Role::Tool => {
+                    let id = message.tool_call_id.as_ref().unwrap();
+                    assert!(open.contains(id), "orphaned result {}: {:?}", id, messages);
+                    open.retain(|v| v != id);
+                }
+                _ => {
+                    assert!(open.is_empty(), "unanswered {:?}: {:?}", open, messages);
+                    let calls = message.tool_calls.iter().flatten();
+                    open.extend(calls.map(|call| call.id.clone()));
+                }
+            }
+        }
+        assert!(open.is_empty(), "unanswered {:?}: {:?}", open, messages);
+    }
+
+    #[test]
+    fn counts() {
+        let encoding = Encoding::O200kBase;
+        assert_eq!(encoding.count_messages(&[]), TOKENS_PER_REPLY);
+        let message = "hello world".user();
+        assert_eq!(
+            encoding.count_message(&message),
+            TOKENS_PER_MESSAGE + encoding.count("user") + encoding.count("hello world")
+        );
+        let tool_calls = calls(&["call_1"]);
+        assert!(encoding.count_message(&tool_calls) > TOKENS_PER_MESSAGE);
+        assert_eq!(Encoding::for_model("gpt-4"), Encoding::Cl100kBase);
+        assert_eq!(Encoding::for_model("gpt-4o"), Encoding::O200kBase);
+        assert_eq!(Encoding::for_model("llama3.2"), Encoding::O200kBase);
+    }
+
+    #[test]
+    fn fits() {
+        let messages = conversation();
+        let budget = ContextBudget::new(100_000).encoding(Encoding::O200kBase);
+        assert!(matches!(
+            budget.fit("gpt-4o", &messages, &[]),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let messages = conversation();
+        let total = Encoding::O200kBase.count_messages(&messages);
+        for max_tokens in 0..=total {
+            let kept = fit(ContextBudget::new(max_tokens), &messages);
+            assert_eq!(kept[..2], messages[..2], "{}", max_tokens);
+            assert_eq!(kept.last(), messages.last());
+            assert!(messages.ends_with(&kept[2..]), "{}: {:?}", max_tokens, kept);
+            assert_paired(&kept);
+            // only the system messages and the last one may be over budget
+            if kept.len() > 3 {
+                assert!(Encoding::O200kBase.count_messages(&kept) <= max_tokens);
+            }
+        }
+        let kept = fit(ContextBudget::new(0), &messages);
+        assert_eq!(kept, [&messages[..2], &messages[7..]].concat());
+    }
+
+    #[test]
+    fn keep_last_n() {
+        let messages = conversation();
+        let last_n = |n| ContextBudget::new(100_000).truncation(Truncation::KeepSystemAndLastN(n));
+        assert_eq!(
+            fit(last_n(2), &messages),
+            [&messages[..2], &messages[6..]].concat()
+        );
+        // the last four would split the tool calls from their results
+        assert_eq!(
+            fit(last_n(4), &messages),
+            [&messages[..2], &messages[6..]].concat()
+        );
+        assert_eq!(
+            fit(last_n(5), &messages),
+            [&messages[..2], &messages[3..]].concat()
+        );
+        assert_eq!(fit(last_n(100), &messages), messages);
+        assert_eq!(
+            fit(last_n(0), &messages),
+            [&messages[..2], &messages[7..]].concat()
+        );
+
+        // then drops the oldest of those that do not fit
+        let tight = ContextBudget::new(0).truncation(Truncation::KeepSystemAndLastN(5));
+        assert_eq!(
+            fit(tight, &messages),
+            [&messages[..2], &messages[7..]].concat()
+        );
+    }
+
+    #[test]
+    fn groups_keep_results_with_calls() {
+        let messages = conversation();
+        let groups = groups(&messages[2..]);
+        let lens: Vec<_> = groups.iter().map(|group| group.len()).collect();
+        assert_eq!(lens, [1, 3, 1, 1]);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conversation::now, ChatOptions, ContextBudget, Conversation, PromptMessage, SummaryMemory,
+    ToolImpl, Usage,
+};
+
+const VERSION: u32 = 1;
+
+/// One line of a transcript: a header first, then every message in order.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Conversation(Box<Header>),
+    Message { at: u64, message: PromptMessage },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    created_at: u64,
+    saved_at: u64,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    options: ChatOptions,
+    // `options` does not serialize these, as they are not sent to the provider
+    #[serde(default)]
+    options_model: Option<String>,
+    #[serde(default)]
+    context_budget: Option<ContextBudget>,
+    #[serde(default)]
+    memory: Option<SummaryMemory>,
+    /// The schemas the model saw; the functions behind them cannot be saved.
+    #[serde(default)]
+    tools: Vec<ToolImpl>,
+    #[serde(default)]
+    usage: Usage,
+}
+
+impl Conversation {
+    /// Writes the conversation as a JSONL transcript, replacing `path`.
+    ///
+    /// The first line holds the model, settings, tool schemas, usage and timestamps,
+    /// every following line one message with the time it was added.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("jsonl.tmp");
+        let file = File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_transcript(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp, path).with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Reads a transcript written by [`Conversation::save`].
+    ///
+    /// Tools are not restored, register them again with [`Conversation::tool`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Conversation> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        Conversation::read_transcript(BufReader::new(file))
+            .with_context(|| format!("load {}", path.display()))
+    }
+
+    pub fn write_transcript(&self, mut writer: impl Write) -> Result<()> {
+        let mut tools: Vec<_> = self.tools.values().map(|(tool, _)| tool.clone()).collect();
+        tools.sort_by(|a, b| match (a, b) {
+            (ToolImpl::Function { name: a, .. }, ToolImpl::Function { name: b, .. }) => a.cmp(b),
+        });
+        let header = Header {
+            version: VERSION,
+            created_at: self.created_at,
+            saved_at: now(),
+            model: self.model.clone(),
+            system: self.system.clone(),
+            options: self.options.clone(),
+            options_model: self.options.model.clone(),
+            context_budget: self.options.context_budget,
+            memory: self.memory.clone(),
+            tools,
+            usage: self.usage,
+        };
+        serde_json::to_writer(&mut writer, &Record::Conversation(Box::new(header)))?;
+        writeln!(writer)?;
+        for (message, at) in self.messages.iter().zip(&self.times) {
+            serde_json::to_writer(
+                &mut writer,
+                &Record::Message {
+                    at: *at,
+                    message: message.clone(),
+                },
+            )?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_transcript(reader: impl BufRead) -> Result<Conversation> {
+        let mut conversation = None;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record =
+                serde_json::from_str(&line).with_context(|| format!("line {}", i + 1))?;
+            match (record, &mut conversation) {
+                (Record::Conversation(header), None) => {
+                    if header.version > VERSION {
+                        bail!("unsupported transcript version {}", header.version);
+                    }
+                    let header = *header;
+                    conversation = Some(Conversation {
+                        system: header.system,
+                        options: ChatOptions {
+                            model: header.options_model,
+                            context_budget: header.context_budget,
+                            ..header.options
+                        },
+                        memory: header.memory,
+                        model: header.model,
+                        usage: header.usage,
+                        created_at: header.created_at,
+                        ..Conversation::default()
+                    });
+                }
+                (Record::Message { at, message }, Some(conversation)) => {
+                    conversation.messages.push(message);
+                    conversation.times.push(at);
+                }
+                (Record::Conversation(_), Some(_)) => bail!("line {}: second header", i + 1),
+                (Record::Message { .. }, None) => bail!("line {}: message before header", i + 1),
+            }
+        }
+        conversation.context("empty transcript")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        ContentPart, Function, IntoPrompt, Parameters, Role, ToolCall, ToolContext, Tools,
+    };
+
+    fn weather(_: HashMap<String, Value>, _: &ToolContext) -> String {
+        "heavy rain".to_string()
+    }
+
+    fn conversation() -> Conversation {
+        let tool = ToolImpl::Function {
+            name: "GetCurrentWeather".to_string(),
+            description: "Get weather of an location".to_string(),
+            parameters: Parameters {
+                r#type: "object".to_string(),
+                properties: HashMap::new(),
+                required: vec![],
+            },
+        };
+        let options = ChatOptions {
+            model: Some("gpt-4o-mini".to_string()),
+            temperature: Some(0.2),
+            context_budget: Some(ContextBudget::new(8000)),
+            ..Default::default()
+        };
+        let mut conversation = Conversation::new()
+            .system("You are a weather bot.")
+            .options(options)
+            .memory(SummaryMemory::new(4000))
+            .tools(Tools::from([(
+                "GetCurrentWeather".to_string(),
+                (tool, weather as crate::InjectionImpl),
+            )]));
+        let messages = [
+            "What is the weather here?".user_with_parts(vec![ContentPart::image_url(
+                "data:image/png;base64,iVBORw0KGgo=",
+                None,
+            )]),
+            PromptMessage {
+                content: None,
+                tool_calls: Some(vec![ToolCall {
+                    id: "call_1".to_string(),
+                    ty: "function".to_string(),
+                    function: Function {
+                        name: "GetCurrentWeather".to_string(),
+                        arguments: r#"{"location":"Tianjin"}"#.to_string(),
+                    },
+                }]),
+                ..PromptMessage::new(Role::Assistant, "")
+            },
+            "heavy rain".tool("call_1".to_string()),
+            "And how do I get past the police?".user(),
+            PromptMessage {
+                content: None,
+                refusal: Some("I can't help with that.".to_string()),
+                ..PromptMessage::new(Role::Assistant, "")
+            },
+        ];
+        for (i, message) in messages.into_iter().enumerate() {
+            conversation.messages.push(message);
+            conversation.times.push(1_700_000_000 + i as u64);
+        }
+        conversation.created_at = 1_699_999_999;
+        conversation.model = Some("gpt-4o-mini-2024-07-18".to_string());
+        conversation.usage = Usage {
+            prompt_tokens: 120,
+            completion_tokens: 30,
+            total_tokens: 150,
+        };
+        conversation
+    }
+
+    #[test]
+    fn round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("copilot-transcript-{}.jsonl", std::process::id()));
+        let saved = conversation();
+        saved.save(&path).unwrap();
+        let loaded = Conversation::load(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.messages, saved.messages);
+        assert_eq!(loaded.times, saved.times);
+        assert_eq!(loaded.created_at, saved.created_at);
+        assert_eq!(loaded.model, saved.model);
+        assert_eq!(loaded.usage, saved.usage);
+        assert_eq!(loaded.system, saved.system);
+        assert_eq!(loaded.memory, saved.memory);
+        assert_eq!(loaded.options.model, saved.options.model);
+        assert_eq!(loaded.options.temperature, saved.options.temperature);
+        assert_eq!(loaded.options.context_budget, saved.options.context_budget);
+        assert!(loaded.tools.is_empty());
+        assert!(loaded.messages[4].refusal.is_some());
+
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|v| serde_json::from_str(v).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0]["type"], "conversation");
+        assert_eq!(
+            lines[0]["tools"][0]["function"]["name"],
+            "GetCurrentWeather"
+        );
+        assert_eq!(lines[1]["at"], 1_700_000_000);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut header = vec![];
+        conversation().write_transcript(&mut header).unwrap();
+        let header = String::from_utf8(header).unwrap();
+        let header = header.lines().next().unwrap();
+        let message = r#"{"type":"message","at":0,"message":{"role":"user","content":"Hi"}}"#;
+
+        let read = |text: String| Conversation::read_transcript(text.as_bytes());
+        assert!(read(String::new()).is_err());
+        assert!(read(format!("{}\n", message)).is_err());
+        assert!(read(format!("{}\n{}\n", header, header)).is_err());
+        let newer = header.replace(r#""version":1"#, r#""version":2"#);
+        assert!(read(newer).is_err());
+        let conversation = read(format!("{}\n\n{}\n", header, message)).unwrap();
+        assert_eq!(conversation.messages.len(), 1);
+    }
+}
@@ -1,28 +1,164 @@
-use std::borrow::Cow;
+use std::{any::Any, borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
 
+use anyhow::Result;
 use copilot_rs_core::ToolImpl;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 
-#[derive(TypedBuilder, Debug, Serialize, Deserialize)]
+use crate::{
+    auth::Auth,
+    cancel::{secs, secs_map},
+    provider::ProviderKind,
+    tokenizer::ContextBudget,
+    Approver, CancellationToken, Content, Middleware, ToolPolicy,
+};
+
+#[derive(TypedBuilder, Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
     pub api_base: String,
-    pub api_key: String,
+    /// Never serialized when it holds a literal secret.
+    #[builder(setter(into))]
+    #[serde(skip_serializing_if = "Auth::is_secret")]
+    pub api_key: Auth,
     pub model_default: String,
+    /// The wire format spoken by `api_base`.
+    #[builder(default)]
+    #[serde(default)]
+    pub provider: ProviderKind,
+    /// Repair malformed JSON in tool arguments and structured outputs before parsing.
+    #[builder(default = true)]
+    #[serde(default = "default_repair_json")]
+    pub repair_json: bool,
+    /// Per tool name, with `*` for the tools not listed. Tools run right away by default.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tool_policies: HashMap<String, ToolPolicy>,
+    /// Decides about the tool calls whose policy is [`ToolPolicy::Ask`].
+    #[builder(default, setter(strip_option))]
+    #[serde(skip)]
+    pub approver: Option<Arc<dyn Approver>>,
+    /// How long a tool may run before the model is told it timed out. No limit by
+    /// default. Tools with a timeout run on a thread of their own, the others on the
+    /// thread of the chat.
+    #[builder(default, setter(strip_option))]
+    #[serde(default, with = "secs", skip_serializing_if = "Option::is_none")]
+    pub tool_timeout: Option<Duration>,
+    /// Overrides `tool_timeout` per tool name.
+    #[builder(default)]
+    #[serde(default, with = "secs_map", skip_serializing_if = "HashMap::is_empty")]
+    pub tool_timeouts: HashMap<String, Duration>,
+    /// Cancel it to stop every chat and tool using this client; set a new one to use
+    /// the client again.
+    #[builder(default)]
+    #[serde(skip)]
+    pub cancellation: CancellationToken,
+    /// Passed to tools implementing [`crate::FunctionImplWithContext`].
+    #[builder(default, setter(skip))]
+    #[serde(skip)]
+    pub state: Option<Arc<dyn Any + Send + Sync>>,
+    /// Hooks around every request and tool call, see [`Middleware`].
+    #[builder(default)]
+    #[serde(skip)]
+    pub middleware: Vec<Arc<dyn Middleware>>,
+    /// Record prompts, completions and tool arguments and results on the spans of the
+    /// `tracing` feature. Also turned on by setting
+    /// `OTEL_INSTRUMENTATION_GENAI_CAPTURE_MESSAGE_CONTENT=true`.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub capture_content: bool,
+}
+
+fn default_repair_json() -> bool {
+    true
+}
+
+impl Client {
+    /// A copy of the client whose tools get `state`, e.g. a database pool or the user
+    /// a request is made for.
+    pub fn with_state<S: Any + Send + Sync>(&self, state: S) -> Client {
+        Client {
+            state: Some(Arc::new(state)),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of the client that runs `middleware` after the middleware it already has.
+    pub fn with_middleware(&self, middleware: impl Middleware + 'static) -> Client {
+        let mut client = self.clone();
+        client.middleware.push(Arc::new(middleware));
+        client
+    }
+
+    pub fn parse_json<T: DeserializeOwned>(&self, input: &str) -> Result<T> {
+        crate::repair::from_str(input, self.repair_json)
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct OpenAIRequest<'a> {
     pub model: String,
     pub messages: Vec<PromptMessage>,
-    pub max_tokens: u32,
-    pub temperature: f32,
     pub stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<&'a ToolImpl>>,
+    #[serde(flatten)]
+    pub options: &'a ChatOptions,
+}
+
+/// Sampling parameters of a completion request. Unset fields are left out of the
+/// request so the provider defaults apply.
+#[derive(TypedBuilder, Debug, Clone, Default, Serialize, Deserialize)]
+#[builder(field_defaults(default, setter(strip_option)))]
+pub struct ChatOptions {
+    /// Overrides `Client::model_default`.
+    #[serde(skip)]
+    #[builder(setter(into))]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Token id to bias in `[-100, 100]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<String, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(into))]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// Drops old messages before sending so the prompt fits.
+    #[serde(skip)]
+    pub context_budget: Option<ContextBudget>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum Role {
     System,
@@ -31,24 +167,86 @@ pub enum Role {
     Tool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PromptMessage {
     pub role: Role,
-    pub content: String,
+    /// `None` when an assistant message only carries tool calls or a refusal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl PromptMessage {
+    pub fn new(role: Role, content: impl Into<Content>) -> Self {
+        PromptMessage {
+            role,
+            content: Some(content.into()),
+            refusal: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn text(&self) -> Cow<'_, str> {
+        self.content
+            .as_ref()
+            .map(Content::as_text)
+            .unwrap_or(Cow::Borrowed(""))
+    }
+}
+
+/// The final answer of a chat: either the assistant's text or a refusal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatOutcome {
+    Message(String),
+    Refusal(String),
+}
+
+impl ChatOutcome {
+    pub fn is_refusal(&self) -> bool {
+        matches!(self, ChatOutcome::Refusal(_))
+    }
+
+    pub fn into_text(self) -> String {
+        match self {
+            ChatOutcome::Message(text) | ChatOutcome::Refusal(text) => text,
+        }
+    }
+}
+
+impl std::fmt::Display for ChatOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatOutcome::Message(text) | ChatOutcome::Refusal(text) => f.write_str(text),
+        }
+    }
+}
+
+impl From<&PromptMessage> for ChatOutcome {
+    fn from(message: &PromptMessage) -> Self {
+        match &message.refusal {
+            Some(refusal) => ChatOutcome::Refusal(refusal.clone()),
+            None => ChatOutcome::Message(message.text().into_owned()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ToolCall {
     pub id: String,
     #[serde(rename = "type")]
     pub ty: String,
     pub function: Function,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
     pub arguments: String,
@@ -61,10 +259,27 @@ pub struct ChatCompletion {
     pub id: String,
     pub model: String,
     pub object: String,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
 }
 
 impl ChatCompletion {
-    pub fn get_content(&self) -> Cow<str> {
+    pub fn get_content(&self) -> Cow<'_, str> {
         if let Some(content) = self.choices[0]
             .delta
             .as_ref()
@@ -72,14 +287,14 @@ impl ChatCompletion {
         {
             Cow::Borrowed(content)
         } else if let Some(msg) = self.choices[0].message.as_ref() {
-            Cow::Borrowed(&msg.content)
+            msg.text()
         } else {
             Cow::Borrowed("")
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct Choice {
     pub delta: Option<Delta>,
     pub message: Option<PromptMessage>,
@@ -0,0 +1,369 @@
+use std::{collections::HashMap, sync::Arc};
+
+use copilot_rs::{
+    provider::Azure, ChatCompletion, ChatOptions, Client, ContentPart, Function, IntoPrompt,
+    OpenAIRequest, Parameters, PromptMessage, Property, Provider, ProviderKind, Role, Secret,
+    ToolCall, ToolImpl,
+};
+use reqwest::blocking::RequestBuilder;
+use serde_json::Value;
+
+fn fixture(name: &str) -> String {
+    let path = format!(
+        "{}/tests/fixtures/provider/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+}
+
+fn weather_tool() -> ToolImpl {
+    ToolImpl::Function {
+        name: "GetCurrentWeather".to_string(),
+        description: "Get weather of an location".to_string(),
+        parameters: Parameters {
+            r#type: "object".to_string(),
+            properties: HashMap::from([(
+                "location".to_string(),
+                Property {
+                    r#type: "string".to_string(),
+                    choices: None,
+                    description: "The city and state".to_string(),
+                },
+            )]),
+            required: vec!["location".to_string()],
+        },
+    }
+}
+
+fn messages() -> Vec<PromptMessage> {
+    vec![
+        "You are a weather bot.".system(),
+        "What is the weather here?".user_with_parts(vec![ContentPart::image_url(
+            "data:image/png;base64,iVBORw0KGgo=",
+            None,
+        )]),
+        PromptMessage {
+            content: None,
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                ty: "function".to_string(),
+                function: Function {
+                    name: "GetCurrentWeather".to_string(),
+                    arguments: r#"{"location":"Tianjin"}"#.to_string(),
+                },
+            }]),
+            ..PromptMessage::new(Role::Assistant, "")
+        },
+        "heavy rain".tool("call_1".to_string()),
+    ]
+}
+
+fn with_provider(provider: ProviderKind) -> Client {
+    Client::builder()
+        .api_base("http://localhost:8080".to_string())
+        .api_key("key".to_string())
+        .model_default("test-model".to_string())
+        .provider(provider)
+        .build()
+}
+
+fn assert_request(provider: ProviderKind, fixture_name: &str) {
+    let tool = weather_tool();
+    let options = ChatOptions::builder()
+        .temperature(0.5)
+        .max_tokens(256)
+        .stop(vec!["END".to_string()])
+        .build();
+    let request = OpenAIRequest {
+        model: "test-model".to_string(),
+        messages: messages(),
+        stream: false,
+        tools: Some(vec![&tool]),
+        options: &options,
+    };
+    let client = with_provider(provider);
+    let body = client
+        .provider
+        .as_provider()
+        .request_body(&client, &request)
+        .unwrap();
+    let expected: Value = serde_json::from_str(&fixture(fixture_name)).unwrap();
+    assert_eq!(
+        body,
+        expected,
+        "{}",
+        serde_json::to_string_pretty(&body).unwrap()
+    );
+}
+
+fn parse(provider: ProviderKind, fixture_name: &str) -> ChatCompletion {
+    provider
+        .as_provider()
+        .parse_response(&fixture(fixture_name))
+        .unwrap()
+}
+
+fn assert_tool_call(res: &ChatCompletion) {
+    let choice = &res.choices[0];
+    assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+    let calls = choice
+        .message
+        .as_ref()
+        .unwrap()
+        .tool_calls
+        .as_ref()
+        .unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].function.name, "GetCurrentWeather");
+    let args: Value = serde_json::from_str(&calls[0].function.arguments).unwrap();
+    assert_eq!(args["location"], "Tianjin");
+}
+
+#[test]
+fn openai_request() {
+    assert_request(ProviderKind::OpenAI, "openai_request.json");
+}
+
+#[test]
+fn openai_response() {
+    let res = parse(ProviderKind::OpenAI, "openai_response.json");
+    assert_tool_call(&res);
+    assert_eq!(res.choices[0].message.as_ref().unwrap().content, None);
+    assert_eq!(res.usage.unwrap().total_tokens, 99);
+}
+
+#[test]
+fn anthropic_request() {
+    assert_request(ProviderKind::Anthropic, "anthropic_request.json");
+}
+
+#[test]
+fn anthropic_response() {
+    let res = parse(ProviderKind::Anthropic, "anthropic_response.json");
+    assert_tool_call(&res);
+    assert_eq!(
+        res.choices[0].message.as_ref().unwrap().text(),
+        "Let me check."
+    );
+    assert_eq!(res.usage.unwrap().total_tokens, 420);
+}
+
+#[test]
+fn gemini_request() {
+    assert_request(ProviderKind::Gemini, "gemini_request.json");
+}
+
+#[test]
+fn gemini_response() {
+    let res = parse(ProviderKind::Gemini, "gemini_response.json");
+    assert_tool_call(&res);
+    assert_eq!(res.usage.unwrap().total_tokens, 75);
+}
+
+fn tool_call(res: &ChatCompletion) -> ToolCall {
+    res.choices[0]
+        .message
+        .as_ref()
+        .unwrap()
+        .tool_calls
+        .as_ref()
+        .unwrap()[0]
+        .clone()
+}
+
+fn request_body(client: &Client, messages: Vec<PromptMessage>) -> anyhow::Result<Value> {
+    let options = ChatOptions::default();
+    let request = OpenAIRequest {
+        model: "test-model".to_string(),
+        messages,
+        stream: false,
+        tools: None,
+        options: &options,
+    };
+    client.provider.as_provider().request_body(client, &request)
+}
+
+#[test]
+fn gemini_two_turns() {
+    let weather = tool_call(&parse(ProviderKind::Gemini, "gemini_response.json"));
+    let time = tool_call(&parse(ProviderKind::Gemini, "gemini_time_response.json"));
+    assert_ne!(weather.id, time.id);
+
+    let assistant = |call: &ToolCall| PromptMessage {
+        content: None,
+        tool_calls: Some(vec![call.clone()]),
+        ..PromptMessage::new(Role::Assistant, "")
+    };
+    let body = request_body(
+        &with_provider(ProviderKind::Gemini),
+        vec![
+            "What is the weather and the time in Tianjin?".user(),
+            assistant(&weather),
+            "heavy rain".tool(weather.id.clone()),
+            assistant(&time),
+            "14:05".tool(time.id.clone()),
+        ],
+    )
+    .unwrap();
+    let expected: Value = serde_json::from_str(&fixture("gemini_two_turns_request.json")).unwrap();
+    assert_eq!(
+        body,
+        expected,
+        "{}",
+        serde_json::to_string_pretty(&body).unwrap()
+    );
+}
+
+#[test]
+fn gemini_file_id() {
+    let message = "Summarize it".user_with_parts(vec![ContentPart::file_id("file-abc123")]);
+    let err = request_body(&with_provider(ProviderKind::Gemini), vec![message]).unwrap_err();
+    assert!(err.to_string().contains("data"), "{}", err);
+}
+
+#[test]
+fn bad_arguments() {
+    let mut messages = messages();
+    messages[2].tool_calls.as_mut().unwrap()[0]
+        .function
+        .arguments = "[1, ".to_string();
+    for provider in [
+        ProviderKind::Anthropic,
+        ProviderKind::Gemini,
+        ProviderKind::Ollama,
+    ] {
+        let messages = match provider {
+            // ollama only takes inline images
+            ProviderKind::Ollama => messages[2..].to_vec(),
+            _ => messages.clone(),
+        };
+        let err = request_body(&with_provider(provider.clone()), messages).unwrap_err();
+        assert!(
+            err.to_string().contains("call_1"),
+            "{:?}: {}",
+            provider,
+            err
+        );
+    }
+}
+
+#[test]
+fn repair_arguments() {
+    let mut messages = messages();
+    messages[2].tool_calls.as_mut().unwrap()[0]
+        .function
+        .arguments = r#"{"location": "Tianjin",}"#.to_string();
+    let messages = messages[2..].to_vec();
+    for provider in [
+        ProviderKind::Anthropic,
+        ProviderKind::Gemini,
+        ProviderKind::Ollama,
+    ] {
+        let mut client = with_provider(provider.clone());
+        let body = request_body(&client, messages.clone()).unwrap();
+        assert!(
+            body.to_string().contains(r#"{"location":"Tianjin"}"#),
+            "{:?}",
+            provider
+        );
+
+        client.repair_json = false;
+        let err = request_body(&client, messages.clone()).unwrap_err();
+        assert!(
+            err.to_string().contains("call_1"),
+            "{:?}: {}",
+            provider,
+            err
+        );
+    }
+}
+
+#[test]
+fn ollama_request() {
+    assert_request(ProviderKind::Ollama, "ollama_request.json");
+}
+
+#[test]
+fn ollama_response() {
+    let res = parse(ProviderKind::Ollama, "ollama_response.json");
+    assert_tool_call(&res);
+    assert_eq!(res.usage.unwrap().total_tokens, 110);
+
+    let again = parse(ProviderKind::Ollama, "ollama_response.json");
+    assert_ne!(tool_call(&res).id, tool_call(&again).id);
+}
+
+struct Echo;
+
+impl Provider for Echo {
+    fn url(&self, client: &Client, _model: &str) -> String {
+        format!("{}/echo", client.api_base)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, _token: &Secret) -> RequestBuilder {
+        builder
+    }
+
+    fn request_body(&self, _client: &Client, request: &OpenAIRequest) -> anyhow::Result<Value> {
+        Ok(serde_json::json!({ "prompt": request.messages[0].text() }))
+    }
+
+    fn parse_response(&self, _body: &str) -> anyhow::Result<ChatCompletion> {
+        Ok(ChatCompletion::default())
+    }
+}
+
+#[test]
+fn custom() {
+    let client = Client::builder()
+        .api_base("http://localhost:8080".to_string())
+        .api_key("key".to_string())
+        .model_default("echo".to_string())
+        .provider(ProviderKind::Custom(Arc::new(Echo)))
+        .build();
+    let provider = client.provider.as_provider();
+    assert_eq!(provider.url(&client, "echo"), "http://localhost:8080/echo");
+    let body = request_body(&client, vec!["Hi".user()]).unwrap();
+    assert_eq!(body["prompt"], "Hi");
+
+    assert_eq!(client.provider, client.provider.clone());
+    assert_ne!(client.provider, ProviderKind::Custom(Arc::new(Echo)));
+    assert!(serde_json::to_string(&client).is_err());
+}
+
+#[test]
+fn azure_url() {
+    let client = Client::builder()
+        .api_base("https://my-resource.openai.azure.com/".to_string())
+        .api_key("key".to_string())
+        .model_default("gpt-4o".to_string())
+        .build();
+    let azure = ProviderKind::Azure(Azure {
+        deployment: Some("prod-gpt4o".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(
+        azure.as_provider().url(&client, "gpt-4o"),
+        "https://my-resource.openai.azure.com/openai/deployments/prod-gpt4o/chat/completions?api-version=2024-10-21"
+    );
+    let by_model = ProviderKind::Azure(Azure::default());
+    assert!(by_model
+        .as_provider()
+        .url(&client, "gpt-4o")
+        .contains("/deployments/gpt-4o/"));
+}
+
+#[test]
+fn azure_config() {
+    let provider: ProviderKind =
+        serde_json::from_str(r#"{"azure": {"deployment": "prod", "entra": true}}"#).unwrap();
+    assert_eq!(
+        provider,
+        ProviderKind::Azure(Azure {
+            deployment: Some("prod".to_string()),
+            api_version: "2024-10-21".to_string(),
+            entra: true,
+        })
+    );
+}
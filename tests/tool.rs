@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use copilot_rs::{FunctionImplTrait, FunctionImplWithContext, FunctionTool, ToolContext, ToolImpl};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(FunctionTool, Deserialize, Serialize)]
+#[props(desc = "Get weather of an location")]
+struct GetCurrentWeather {
+    #[props(desc = "The city and state")]
+    location: String,
+}
+
+impl FunctionImplTrait for GetCurrentWeather {
+    fn exec(&self) -> String {
+        format!("heavy rain in {}", self.location)
+    }
+}
+
+struct Db {
+    user: String,
+}
+
+#[derive(FunctionTool, Deserialize, Serialize)]
+#[props(desc = "List the orders of the current user", state = "Db")]
+struct ListOrders {
+    #[props(desc = "How many orders to list")]
+    limit: u32,
+}
+
+impl FunctionImplWithContext<Db> for ListOrders {
+    fn exec(&self, ctx: &ToolContext<Db>) -> String {
+        format!("{} orders of {}", self.limit, ctx.state().user)
+    }
+}
+
+fn args(value: Value) -> HashMap<String, Value> {
+    serde_json::from_value(value).unwrap()
+}
+
+fn ctx(state: Option<Db>) -> ToolContext {
+    let state = state.map(|v| std::sync::Arc::new(v) as _);
+    ToolContext::new("tool", "call_1", state)
+}
+
+#[test]
+fn inject() {
+    let output = GetCurrentWeather::inject(args(json!({ "location": "Tianjin" })), &ctx(None));
+    assert_eq!(output, "heavy rain in Tianjin");
+
+    let db = Db {
+        user: "dan".to_string(),
+    };
+    let output = ListOrders::inject(args(json!({ "limit": 3 })), &ctx(Some(db)));
+    assert_eq!(output, "3 orders of dan");
+}
+
+#[test]
+fn invalid_arguments() {
+    let output = GetCurrentWeather::inject(args(json!({})), &ctx(None));
+    assert_eq!(
+        output,
+        "error: invalid arguments for GetCurrentWeather: missing field `location`"
+    );
+
+    let output = GetCurrentWeather::inject(args(json!({ "location": 7 })), &ctx(None));
+    assert!(
+        output.starts_with("error: invalid arguments for GetCurrentWeather: invalid type"),
+        "{}",
+        output
+    );
+
+    let db = Db {
+        user: "dan".to_string(),
+    };
+    let output = ListOrders::inject(args(json!({ "limit": -1 })), &ctx(Some(db)));
+    assert!(
+        output.starts_with("error: invalid arguments for ListOrders"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn missing_state() {
+    let output = ListOrders::inject(args(json!({ "limit": 3 })), &ctx(None));
+    assert_eq!(
+        output,
+        "error: the tool ListOrders needs client state of type Db"
+    );
+}
@@ -0,0 +1,8 @@
+use copilot_rs::complete;
+
+#[complete(temperature = 0.5)]
+fn ask() -> String {
+    vec![].chat()
+}
+
+fn main() {}
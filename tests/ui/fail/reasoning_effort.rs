@@ -0,0 +1,8 @@
+use copilot_rs::complete;
+
+#[complete(client = client, reasoning_effort = "extreme")]
+fn ask(client: &copilot_rs::Client) -> String {
+    vec![].chat()
+}
+
+fn main() {}
@@ -0,0 +1,35 @@
+use copilot_rs::{complete, Client, IntoPrompt};
+
+fn client() -> Client {
+    Client::from_env().unwrap()
+}
+
+#[complete(client = "client")]
+fn by_function(name: &str) -> String {
+    vec![format!("Hello {}", name).user()].chat()
+}
+
+#[complete(client = Client::from_env().unwrap(), model = "gpt-4o")]
+fn by_expression() -> String {
+    ["Hi".user()].chat()
+}
+
+#[complete(client = client)]
+fn by_parameter(client: &Client) -> String {
+    vec!["Hi".user()].chat()
+}
+
+struct Assistant {
+    client: Client,
+}
+
+impl Assistant {
+    #[complete(client = self.client, temperature = 0.2, seed = 7)]
+    fn ask(&self, question: &str) -> String {
+        vec![question.user()].chat()
+    }
+}
+
+fn main() {
+    let _ = (by_function, by_expression, by_parameter, Assistant::ask);
+}
@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+use copilot_rs::{complete, Client, IntoMessages, IntoPrompt, PromptMessage};
+
+// names the old expansion used must not clash with the generated code
+#[allow(dead_code)]
+trait Chat {}
+#[allow(dead_code)]
+struct ChatOptions;
+
+#[complete(client = client, stop = ["\n"], reasoning_effort = "low")]
+fn rewrite(client: &Client, history: &VecDeque<PromptMessage>, retry: bool) -> String {
+    let options = "not the chat options";
+    if retry {
+        return format!("{}: {}", options, history.chat());
+    }
+    let messages = ["Hi".user()];
+    let first = messages.chat();
+    let boxed: Box<[PromptMessage]> = Box::new(["Again".user()]);
+    first + &boxed.chat()
+}
+
+#[complete(client = client)]
+async fn later(client: &Client) -> String {
+    vec!["Hi".user()].async_chat().await
+}
+
+#[complete(client = client)]
+fn recent(client: &Client, history: &[PromptMessage]) -> String {
+    let recent = history.iter().rev().take(4).rev();
+    recent.messages().chat()
+}
+
+#[complete(client = client)]
+fn outcome(client: &Client) -> anyhow::Result<String> {
+    let outcome = vec!["Hi".user()].chat_outcome()?;
+    if outcome.is_refusal() {
+        anyhow::bail!("refused: {}", outcome);
+    }
+    Ok(outcome.into_text())
+}
+
+// the same name twice must expand to the same, non-conflicting code
+mod a {
+    #[copilot_rs::complete(client = client)]
+    pub fn ask(client: &copilot_rs::Client) -> String {
+        Vec::<copilot_rs::PromptMessage>::new().chat()
+    }
+}
+
+mod b {
+    #[copilot_rs::complete(client = client)]
+    pub fn ask(client: &copilot_rs::Client) -> String {
+        Vec::<copilot_rs::PromptMessage>::new().chat()
+    }
+}
+
+fn main() {
+    let _ = (rewrite, later, recent, outcome, a::ask, b::ask);
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use copilot_rs::{
+    complete, Client, FunctionImplTrait, FunctionImplWithContext, FunctionTool, IntoPrompt,
+    ToolContext, ToolImpl,
+};
+use serde::{Deserialize, Serialize};
+
+struct AppState {
+    user_id: u64,
+}
+
+#[derive(FunctionTool, Deserialize, Serialize)]
+#[props(desc = "List the orders of the current user", state = "Arc<AppState>")]
+struct ListOrders {
+    #[props(desc = "How many orders to list")]
+    limit: u32,
+}
+
+impl FunctionImplWithContext<Arc<AppState>> for ListOrders {
+    fn exec(&self, ctx: &ToolContext<Arc<AppState>>) -> String {
+        format!("{} orders of user {}", self.limit, ctx.state().user_id)
+    }
+}
+
+#[derive(FunctionTool, Deserialize, Serialize)]
+#[props(desc = "Get the time")]
+struct Now {
+    #[props(desc = "The time zone")]
+    zone: String,
+}
+
+impl FunctionImplTrait for Now {
+    fn exec(&self) -> String {
+        self.zone.clone()
+    }
+}
+
+#[complete(client = client.with_state(Arc::new(AppState { user_id })), tools = ["ListOrders", "Now"])]
+fn orders(client: &Client, user_id: u64) -> String {
+    vec!["What did I order?".user()].chat()
+}
+
+fn main() {
+    let _ = orders;
+}